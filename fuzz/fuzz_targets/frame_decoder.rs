@@ -0,0 +1,50 @@
+#![no_main]
+
+use bytes::BytesMut;
+use crate_net::dccnet::{
+    codec::DccnetCodec,
+    detection::Detection,
+    fec::Fec,
+    network::{FrameFormat, Payload},
+};
+use libfuzzer_sys::fuzz_target;
+use tokio_util::codec::Decoder;
+
+// The first byte picks the frame format, the second the size of each read
+fuzz_target!(|data: &[u8]| {
+    let [selector, chunk, stream @ ..] = data else {
+        return;
+    };
+
+    let format = FrameFormat {
+        strict_flags: selector & 0x01 != 0,
+        detection: match (selector >> 1) % 3 {
+            0 => Detection::Internet,
+            1 => Detection::Crc16,
+            _ => Detection::Crc32,
+        },
+        fec: match selector & 0x08 != 0 {
+            true => Some(Fec::new(8).unwrap()),
+            false => None,
+        },
+        ..FrameFormat::default()
+    };
+
+    let mut codec = DccnetCodec::with_format(format);
+    let mut buf = BytesMut::new();
+
+    for piece in stream.chunks(*chunk as usize + 1) {
+        buf.extend_from_slice(piece);
+
+        // Every invalid frame skips at least one byte, so this loop always terminates
+        while let Some(result) = codec.decode(&mut buf).unwrap() {
+            if let Ok(payload) = result {
+                let bytes = payload.as_bytes_with(&format);
+                let reparsed = Payload::from_bytes_with(&bytes, &format).unwrap();
+                assert_eq!(reparsed.data, payload.data);
+            }
+        }
+    }
+
+    let _ = codec.decode_eof(&mut buf);
+});
@@ -0,0 +1,15 @@
+#![no_main]
+
+use crate_net::dccnet::network::{FrameFormat, Payload};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Payload::from_bytes_strict(data);
+
+    if let Ok(payload) = Payload::from_bytes(data) {
+        // A frame that parses must encode back to exactly the bytes it came from
+        let size = FrameFormat::default().frame_size(payload.data.len());
+        assert_eq!(payload.length as usize, payload.data.len());
+        assert_eq!(payload.as_bytes(), &data[..size]);
+    }
+});
@@ -0,0 +1,48 @@
+#![no_main]
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    time::Duration,
+};
+
+use crate_net::dccnet::{config::DccnetConfig, xfer};
+use libfuzzer_sys::fuzz_target;
+use once_cell::sync::Lazy;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    runtime::Runtime,
+    time::timeout,
+};
+
+static RUNTIME: Lazy<Runtime> = Lazy::new(|| Runtime::new().unwrap());
+
+// Plays a peer that writes arbitrary bytes into a real connection handled by xfer
+fuzz_target!(|data: &[u8]| {
+    RUNTIME.block_on(async {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let peer = async {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream.write_all(data).await.unwrap();
+            stream.shutdown().await.unwrap();
+
+            let mut sink = Vec::new();
+            let _ = stream.read_to_end(&mut sink).await;
+        };
+
+        let local = async {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut input = BufReader::new(File::open("/dev/null").unwrap());
+            let mut output = BufWriter::new(File::create("/dev/null").unwrap());
+
+            xfer::handle_connection(stream, &mut input, &mut output, &DccnetConfig::default()).await;
+        };
+
+        timeout(Duration::from_secs(30), async { tokio::join!(peer, local) })
+            .await
+            .expect("receive pipeline did not finish");
+    });
+});
@@ -1,11 +1,30 @@
 use crate_net::dccnet;
+use crate_net::dccnet::config::DccnetConfig;
 use tokio::net::TcpStream;
+use tokio::time::timeout;
 
 use std::env;
 
+fn parse_options(options: &[String]) -> DccnetConfig {
+    let mut config = DccnetConfig::default();
+
+    for option in options {
+        let (name, value) = option.split_once('=').unwrap_or((option, ""));
+
+        config.parse_option(name, value).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+    }
+
+    config
+}
+
 #[tokio::main]
 async fn main() {
-    let args: Vec<String> = env::args().collect();
+    let (args, options): (Vec<String>, Vec<String>) =
+        env::args().partition(|arg| !arg.starts_with("--"));
+    let config = parse_options(&options);
 
     let server: Vec<&str> = args.get(1).unwrap().split(":").collect();
     let gas = args.get(2).unwrap().as_bytes().to_vec();
@@ -25,12 +44,17 @@ async fn main() {
         }
     };
 
-    let communication_result = match TcpStream::connect((server_address, server_port)).await {
-        Ok(stream) => dccnet::md5::handle_tcp_communication(stream, gas).await,
-        Err(e) => {
+    let connect = TcpStream::connect((server_address, server_port));
+    let communication_result = match timeout(config.connect_timeout, connect).await {
+        Ok(Ok(stream)) => dccnet::md5::handle_tcp_communication(stream, gas, &config).await,
+        Ok(Err(e)) => {
             eprintln!("Failed to connect to the server: {:?}", e.to_string());
             std::process::exit(1);
         }
+        Err(_) => {
+            eprintln!("Failed to connect to the server: timed out");
+            std::process::exit(1);
+        }
     };
 
     if let Err(e) = communication_result {
@@ -5,9 +5,11 @@ use std::path::PathBuf;
 use std::process;
 
 use crate_net::dccnet::client;
+use crate_net::dccnet::config::DccnetConfig;
+use crate_net::dccnet::network;
 use crate_net::dccnet::server;
 
-async fn parse_server_args(args: &[String]) {
+async fn parse_server_args(args: &[String], config: &DccnetConfig) {
     let port = args[2].parse::<u16>().unwrap_or_else(|_| {
         eprintln!("Invalid port: {}", args[2]);
         process::exit(1);
@@ -28,7 +30,12 @@ async fn parse_server_args(args: &[String]) {
     let input = BufReader::new(input_file);
     let output = BufWriter::new(output_file);
 
-    server::run_server(port, input, output).await;
+    server::run_server(port, input, output, config)
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("Error in server: {}", e);
+            process::exit(1);
+        });
 }
 
 fn parse_address(addr: &str) -> (String, String) {
@@ -55,7 +62,7 @@ fn parse_address(addr: &str) -> (String, String) {
     }
 }
 
-async fn parse_client_args(args: &[String]) {
+async fn parse_client_args(args: &[String], config: &DccnetConfig) {
     let (server_ip, server_port) = parse_address(&args[2]);
 
     let server_port = server_port.parse::<u16>().unwrap_or_else(|_| {
@@ -78,31 +85,100 @@ async fn parse_client_args(args: &[String]) {
     let input = BufReader::new(input_file);
     let output = BufWriter::new(output_file);
 
-    client::run_client((server_ip, server_port), input, output)
+    client::run_client((server_ip, server_port), input, output, config)
         .await
         .unwrap_or_else(|e| {
             eprintln!("Error in client: {}", e);
+            process::exit(1);
         });
 }
 
+fn parse_options(options: &[String]) -> DccnetConfig {
+    let mut config = DccnetConfig::default();
+
+    for option in options {
+        let (name, value) = option.split_once('=').unwrap_or((option, ""));
+
+        match name {
+            "--strict" => config.format.strict_flags = true,
+            "--mtu" => {
+                config.format.mtu = match value.parse::<usize>() {
+                    Ok(mtu) if (1..=network::MAX_MTU).contains(&mtu) => mtu,
+                    _ => {
+                        eprintln!("Invalid MTU: {} (must be 1 to {})", value, network::MAX_MTU);
+                        process::exit(1);
+                    }
+                }
+            }
+            "--negotiate-mtu" => config.format.negotiate_mtu = true,
+            "--piggyback" => config.format.piggyback = true,
+            "--nak" => config.format.nak = true,
+            "--flow-control" => config.format.flow_control = true,
+            "--end-ack" => config.format.end_ack = true,
+            "--detector" => {
+                config.format.detection = value.parse().unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                })
+            }
+            "--fec" => {
+                let fec = value.parse().unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                });
+                config.format.fec = Some(fec);
+            }
+            "--arq" => {
+                config.format.arq = value.parse().unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                })
+            }
+            _ => config.parse_option(name, value).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                process::exit(1);
+            }),
+        }
+    }
+
+    config
+}
+
 fn print_usage_and_exit(program_name: &str) {
     eprintln!("Usage:");
-    eprintln!("  {} -s <PORT> <INPUT> <OUTPUT>", program_name);
-    eprintln!("  {} -c <IP>:<PORT> <INPUT> <OUTPUT>", program_name);
+    eprintln!("  {} -s <PORT> <INPUT> <OUTPUT> [OPTIONS]", program_name);
+    eprintln!("  {} -c <IP>:<PORT> <INPUT> <OUTPUT> [OPTIONS]", program_name);
+    eprintln!("Options:");
+    eprintln!("  --mtu=<BYTES>              Largest data size per frame (default: 4096)");
+    eprintln!("  --negotiate-mtu            Agree on the smaller MTU with the peer");
+    eprintln!("  --strict                   Reject frames with flag bits not enabled");
+    eprintln!("  --piggyback                Carry ACKs on outgoing data frames");
+    eprintln!("  --nak                      Ask for corrupted frames right away");
+    eprintln!("  --flow-control             Advertise free receive queue room in ACKs");
+    eprintln!("  --end-ack                  Acknowledge END and retransmit it until acknowledged");
+    eprintln!("  --detector=<internet|crc16|crc32>");
+    eprintln!("                             Error detection code (default: internet)");
+    eprintln!("  --fec=<PARITY>             Reed-Solomon parity bytes per 255-byte block");
+    eprintln!("  --arq=<stop-and-wait|go-back-n|selective-repeat>");
+    eprintln!("                             Retransmission scheme (default: stop-and-wait)");
+    eprintln!("{}", DccnetConfig::usage());
     process::exit(1);
 }
 
 #[tokio::main]
 async fn main() {
-    let args: Vec<String> = env::args().collect();
+    let (args, options): (Vec<String>, Vec<String>) =
+        env::args().partition(|arg| !arg.starts_with("--"));
 
     if args.len() != 5 {
         print_usage_and_exit(&args[0]);
     }
 
+    let config = parse_options(&options);
+
     match args[1].as_str() {
-        "-s" => parse_server_args(&args).await,
-        "-c" => parse_client_args(&args).await,
+        "-s" => parse_server_args(&args, &config).await,
+        "-c" => parse_client_args(&args, &config).await,
         _ => {
             eprintln!("Invalid mode: {}", args[1]);
             print_usage_and_exit(&args[0]);
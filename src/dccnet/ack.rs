@@ -0,0 +1,239 @@
+use std::{
+    fmt,
+    str::FromStr,
+    sync::{
+        Mutex, MutexGuard,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use tokio::time::sleep;
+
+/// Longest an ACK held back for the frames queued behind it waits for them.
+pub const MAX_BATCH_DELAY: Duration = Duration::from_millis(50);
+
+/// When the receive path acknowledges data frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AckPolicy {
+    /// Every frame right away.
+    #[default]
+    Immediate,
+    /// Every frame after the given delay, unless a later ACK replaces it first.
+    Delayed(Duration),
+    /// Once every K frames, or as soon as no more received frames are queued.
+    EveryK(usize),
+    /// Once the received frames queued so far have all been handled.
+    Cumulative,
+}
+
+impl FromStr for AckPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Unknown ACK policy: {}", s);
+
+        match s.split_once(':') {
+            None if s == "immediate" => Ok(AckPolicy::Immediate),
+            None if s == "cumulative" => Ok(AckPolicy::Cumulative),
+            Some(("delayed", millis)) => match millis.parse::<u64>() {
+                Ok(millis) if millis > 0 => Ok(AckPolicy::Delayed(Duration::from_millis(millis))),
+                _ => Err(invalid()),
+            },
+            Some(("every", count)) => match count.parse::<usize>() {
+                Ok(count) if count > 0 => Ok(AckPolicy::EveryK(count)),
+                _ => Err(invalid()),
+            },
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl AckPolicy {
+    /// How long an ACK is held back, given how many ACKs the pending one stands
+    /// for and whether received frames are queued behind it, which will
+    /// acknowledge it too. `None` sends it right away.
+    pub fn delay(&self, held: usize, queued: bool) -> Option<Duration> {
+        match *self {
+            AckPolicy::Delayed(delay) => Some(delay),
+            AckPolicy::EveryK(count) if held < count && queued => Some(MAX_BATCH_DELAY),
+            AckPolicy::Cumulative if queued => Some(MAX_BATCH_DELAY),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for AckPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AckPolicy::Immediate => write!(f, "immediate"),
+            AckPolicy::Delayed(delay) => write!(f, "delayed:{}", delay.as_millis()),
+            AckPolicy::EveryK(count) => write!(f, "every:{}", count),
+            AckPolicy::Cumulative => write!(f, "cumulative"),
+        }
+    }
+}
+
+/// The ACK a session holds back, because of its [`AckPolicy`] or to let it
+/// ride on a data frame, and counts of the ACKs it sent.
+pub struct PendingAck {
+    id: Mutex<Option<u16>>,
+    /// ACKs replaced by the pending one since an ACK was last sent, itself included.
+    held: AtomicUsize,
+    /// Whether the sender may write a data frame soon, rather than being
+    /// blocked on an ACK itself or done.
+    carrier: AtomicBool,
+    sent: AtomicUsize,
+    piggybacked: AtomicUsize,
+}
+
+impl Default for PendingAck {
+    fn default() -> Self {
+        Self {
+            id: Mutex::new(None),
+            held: AtomicUsize::new(0),
+            carrier: AtomicBool::new(true),
+            sent: AtomicUsize::new(0),
+            piggybacked: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl PendingAck {
+    pub fn carrier(&self) -> bool {
+        self.carrier.load(Ordering::Acquire)
+    }
+
+    pub fn set_carrier(&self, carrier: bool) {
+        self.carrier.store(carrier, Ordering::Release);
+    }
+
+    fn slot(&self) -> MutexGuard<'_, Option<u16>> {
+        self.id.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Makes `id` the pending ACK and returns the one it replaces.
+    pub fn replace(&self, id: u16) -> Option<u16> {
+        let mut slot = self.slot();
+        self.held.fetch_add(1, Ordering::Relaxed);
+        slot.replace(id)
+    }
+
+    /// How many ACKs the pending one stands for.
+    pub fn held(&self) -> usize {
+        self.held.load(Ordering::Relaxed)
+    }
+
+    pub fn take(&self) -> Option<u16> {
+        let mut slot = self.slot();
+        self.held.store(0, Ordering::Relaxed);
+        slot.take()
+    }
+
+    /// Takes the pending ACK only if it is still `id`.
+    pub fn take_if(&self, id: u16) -> bool {
+        let mut slot = self.slot();
+        let taken = slot.take_if(|pending| *pending == id).is_some();
+        if taken {
+            self.held.store(0, Ordering::Relaxed);
+        }
+        taken
+    }
+
+    /// Waits for `delay` and then takes the pending ACK if it is still `id`,
+    /// not replaced by a later one or sent meanwhile.
+    pub async fn take_after(&self, id: u16, delay: Duration) -> bool {
+        sleep(delay).await;
+        self.take_if(id)
+    }
+
+    /// Counts an ACK sent on its own, or on a data frame if `piggybacked`.
+    pub fn record_sent(&self, piggybacked: bool) {
+        let counter = if piggybacked {
+            &self.piggybacked
+        } else {
+            &self.sent
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// ACK frames sent on their own.
+    pub fn sent(&self) -> usize {
+        self.sent.load(Ordering::Relaxed)
+    }
+
+    /// ACKs that rode on a data frame.
+    pub fn piggybacked(&self) -> usize {
+        self.piggybacked.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policies_parse_and_display() {
+        for (text, policy) in [
+            ("immediate", AckPolicy::Immediate),
+            ("cumulative", AckPolicy::Cumulative),
+            ("every:4", AckPolicy::EveryK(4)),
+            ("delayed:40", AckPolicy::Delayed(Duration::from_millis(40))),
+        ] {
+            assert_eq!(text.parse::<AckPolicy>(), Ok(policy));
+            assert_eq!(policy.to_string(), text);
+        }
+    }
+
+    #[test]
+    fn invalid_policies_are_rejected() {
+        for text in ["every:0", "every:", "delayed:0", "delayed:soon", "lazy", ""] {
+            assert!(text.parse::<AckPolicy>().is_err(), "{text} was accepted");
+        }
+    }
+
+    #[test]
+    fn every_k_acknowledges_every_kth_frame_while_frames_are_queued() {
+        let policy = AckPolicy::EveryK(3);
+        let pending = PendingAck::default();
+
+        let mut sent = Vec::new();
+        for id in 0..7 {
+            pending.replace(id);
+            if policy.delay(pending.held(), true).is_none() && pending.take_if(id) {
+                sent.push(id);
+            }
+        }
+        assert_eq!(sent, [2, 5]);
+
+        // Nothing else is queued, so the held ACK goes out right away
+        assert_eq!(policy.delay(pending.held(), false), None);
+        assert_eq!(pending.take(), Some(6));
+    }
+
+    #[test]
+    fn cumulative_waits_only_while_frames_are_queued() {
+        assert_eq!(AckPolicy::Cumulative.delay(5, true), Some(MAX_BATCH_DELAY));
+        assert_eq!(AckPolicy::Cumulative.delay(5, false), None);
+        assert_eq!(AckPolicy::Immediate.delay(1, true), None);
+    }
+
+    #[tokio::test]
+    async fn delayed_ack_is_flushed_unless_replaced() {
+        let delay = Duration::from_millis(10);
+        let policy = AckPolicy::Delayed(delay);
+        let pending = PendingAck::default();
+        assert_eq!(policy.delay(1, false), Some(delay));
+
+        pending.replace(1);
+        assert!(pending.take_after(1, delay).await);
+        assert_eq!(pending.take(), None);
+
+        pending.replace(2);
+        let (flushed, ()) = tokio::join!(pending.take_after(2, delay), async {
+            pending.replace(3);
+        });
+        assert!(!flushed);
+        assert!(pending.take_after(3, delay).await);
+    }
+}
@@ -0,0 +1,514 @@
+use std::{collections::VecDeque, fmt, io::Read, str::FromStr, time::Duration};
+
+use tokio::time::Instant;
+
+use super::{
+    communication::{self, NetworkError, NetworkErrorKind},
+    config::DccnetConfig,
+    network::{self, Flags, FrameFormat, Payload},
+    options::FrameOption,
+    session::Session,
+    sync_read::Received,
+};
+
+/// Largest window for which 16-bit ids still tell old frames from new ones.
+pub const MAX_WINDOW: usize = 0x8000;
+
+/// Retransmission scheme used for data frames. Both ends must use the same one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Arq {
+    /// One frame in flight with ids alternating between 0 and 1, as in the specification.
+    #[default]
+    StopAndWait,
+    /// A window of frames in flight numbered with the full 16-bit id. ACKs are
+    /// cumulative and a timeout retransmits every outstanding frame.
+    GoBackN,
+    /// Like [`Arq::GoBackN`], but every frame is acknowledged and retransmitted
+    /// on its own and the receiver buffers frames that arrive out of order.
+    SelectiveRepeat,
+}
+
+impl FromStr for Arq {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stop-and-wait" => Ok(Arq::StopAndWait),
+            "go-back-n" => Ok(Arq::GoBackN),
+            "selective-repeat" => Ok(Arq::SelectiveRepeat),
+            _ => Err(format!("Unknown ARQ mode: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Arq {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Arq::StopAndWait => write!(f, "stop-and-wait"),
+            Arq::GoBackN => write!(f, "go-back-n"),
+            Arq::SelectiveRepeat => write!(f, "selective-repeat"),
+        }
+    }
+}
+
+struct Outstanding {
+    payload: Payload,
+    sent_at: Instant,
+    /// Transmissions so far, including the first one.
+    transmissions: usize,
+    /// Transmissions the frame caused itself, the first one and those after its
+    /// own timeout or NAK, which are the ones limited by `max_send_attempts`.
+    /// Go-Back-N also resends the frames behind a lost one, which do not count.
+    attempts: usize,
+    acked: bool,
+    /// Waiting to be resent once the window has room for it.
+    queued: bool,
+}
+
+impl Outstanding {
+    async fn resend(&mut self, session: &Session) -> Result<(), NetworkError> {
+        // Flip the copy bit so a NAK of the previous copy can be told apart
+        if session.writer().format().nak {
+            let copy = match self.transmissions % 2 {
+                0 => Flags::NONE,
+                _ => Flags::COPY,
+            };
+            let flag = self.payload.flag.difference(Flags::COPY) | copy;
+            let data = self.payload.data.clone();
+            self.payload = session.writer().payload(data, self.payload.id, flag);
+        }
+
+        communication::write_frame(session, &self.payload).await?;
+        self.sent_at = Instant::now();
+        self.transmissions += 1;
+        self.queued = false;
+
+        Ok(())
+    }
+
+    /// Counts another attempt at getting this frame through, and returns how
+    /// many retransmissions it caused before, unless it ran out of attempts.
+    fn retry(&mut self, max_send_attempts: usize) -> Result<usize, NetworkError> {
+        if self.attempts >= max_send_attempts {
+            return Err(NetworkError::new(
+                NetworkErrorKind::RetransmissionError,
+                "Failed to send frame after maximum attempts",
+            ));
+        }
+
+        let retransmissions = self.attempts - 1;
+        self.attempts += 1;
+        Ok(retransmissions)
+    }
+
+    fn in_flight(&self) -> bool {
+        !self.acked && !self.queued
+    }
+}
+
+/// Frames sent but not yet acknowledged, oldest first.
+struct SendWindow {
+    arq: Arq,
+    frames: VecDeque<Outstanding>,
+    size: usize,
+    /// Frames the receiver advertised room for in its last ACK, if it does.
+    peer_window: Option<usize>,
+    next_id: u16,
+}
+
+impl SendWindow {
+    fn new(format: &FrameFormat, size: usize) -> Self {
+        Self {
+            arq: format.arq,
+            frames: VecDeque::with_capacity(size),
+            size,
+            // The room of the receiver is unknown until its first ACK
+            peer_window: format.flow_control.then_some(1),
+            next_id: network::START_ID,
+        }
+    }
+
+    /// Frames that may be in flight within the congestion window and the room
+    /// the receiver advertised. One frame is always allowed, so a receiver that
+    /// advertised no room is probed until it has some again.
+    fn limit(&self, cwnd: usize) -> usize {
+        match self.peer_window {
+            Some(peer_window) => cwnd.min(peer_window),
+            None => cwnd,
+        }
+        .max(1)
+    }
+
+    fn in_flight(&self) -> usize {
+        self.frames.iter().filter(|frame| frame.in_flight()).count()
+    }
+
+    /// Whether a new frame may be sent, which waits for queued retransmissions.
+    fn is_open(&self, cwnd: usize) -> bool {
+        self.frames.len() < self.size
+            && self.in_flight() < self.limit(cwnd)
+            && !self.frames.iter().any(|frame| frame.queued)
+    }
+
+    /// Resends the frames queued for retransmission, oldest first, as far as
+    /// the window allows.
+    async fn resend_queued(&mut self, session: &Session, cwnd: usize) -> Result<(), NetworkError> {
+        let room = self.limit(cwnd).saturating_sub(self.in_flight());
+
+        for frame in self.frames.iter_mut().filter(|frame| frame.queued).take(room) {
+            frame.resend(session).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Takes the room the receiver advertises in an ACK, even a stale one.
+    fn update_peer_window(&mut self, ack: &Payload) {
+        if !ack.flag.contains(Flags::OPT) {
+            return;
+        }
+
+        let window = FrameOption::decode_all(&ack.data)
+            .into_iter()
+            .find_map(|option| match option {
+                FrameOption::Window(window) => Some(window as usize),
+                _ => None,
+            });
+        if let Some(window) = window
+            && self.peer_window != Some(window)
+        {
+            println!("PEER WINDOW {}", window);
+            self.peer_window = Some(window);
+        }
+    }
+
+    fn base(&self) -> u16 {
+        self.frames
+            .front()
+            .map_or(self.next_id, |frame| frame.payload.id)
+    }
+
+    async fn send(&mut self, session: &Session, data: Vec<u8>) -> Result<(), NetworkError> {
+        let payload = session.writer().payload(data, self.next_id, Flags::NONE);
+        communication::write_frame(session, &payload).await?;
+
+        self.frames.push_back(Outstanding {
+            payload,
+            sent_at: Instant::now(),
+            transmissions: 1,
+            attempts: 1,
+            acked: false,
+            queued: false,
+        });
+        self.next_id = self.next_id.wrapping_add(1);
+
+        Ok(())
+    }
+
+    /// Marks the frames covered by the ACK and slides the window past the
+    /// acknowledged ones at its start. Go-Back-N ACKs cover every frame up to
+    /// and including their id, Selective Repeat ACKs only the frame itself.
+    fn acknowledge(&mut self, session: &Session, ack: &Payload) {
+        self.update_peer_window(ack);
+
+        let offset = ack.id.wrapping_sub(self.base()) as usize;
+        let Some(frame) = self.frames.get(offset).filter(|frame| !frame.acked) else {
+            println!("STALE ACK {}", ack);
+            return;
+        };
+        println!("RECV ACK {}", ack);
+
+        session.rto().acknowledged(frame.sent_at.elapsed(), frame.transmissions);
+
+        let covered = match self.arq {
+            Arq::SelectiveRepeat => offset..offset + 1,
+            _ => 0..offset + 1,
+        };
+        let mut newly_acked = 0;
+        for frame in self.frames.range_mut(covered).filter(|frame| !frame.acked) {
+            frame.acked = true;
+            frame.queued = false;
+            newly_acked += 1;
+        }
+
+        while self.frames.front().is_some_and(|frame| frame.acked) {
+            self.frames.pop_front();
+        }
+        session.congestion().on_ack(newly_acked, self.base());
+    }
+
+    /// When the next frame in flight times out.
+    fn deadline(&self, rto: Duration) -> Option<Instant> {
+        let mut in_flight = self.frames.iter().filter(|frame| frame.in_flight());
+
+        match self.arq {
+            Arq::SelectiveRepeat => in_flight.map(|frame| frame.sent_at).min(),
+            _ => in_flight.next().map(|frame| frame.sent_at),
+        }
+        .map(|sent_at| sent_at + rto)
+    }
+
+    /// Queues every frame Go-Back-N goes back over for retransmission, or with
+    /// Selective Repeat the frames in flight that have been waiting for longer
+    /// than `rto`. Only the frames that timed out use up an attempt, which for
+    /// Go-Back-N is the oldest one.
+    fn retransmit(&mut self, rto: Duration, max_send_attempts: usize) -> Result<(), NetworkError> {
+        let now = Instant::now();
+
+        if self.arq != Arq::SelectiveRepeat {
+            let attempt = self.frames[0].retry(max_send_attempts)?;
+            println!("({}) RETRANSMISSION of {} frames", attempt, self.frames.len());
+
+            for frame in self.frames.iter_mut().filter(|frame| !frame.acked) {
+                frame.queued = true;
+            }
+            return Ok(());
+        }
+
+        for frame in self.frames.iter_mut().filter(|frame| frame.in_flight()) {
+            if frame.sent_at + rto > now {
+                continue;
+            }
+
+            let attempt = frame.retry(max_send_attempts)?;
+            println!("({}) RETRANSMISSION of frame {}", attempt, frame.payload.id);
+            frame.queued = true;
+        }
+
+        Ok(())
+    }
+
+    /// Queues the frame a NAK refers to for retransmission right away, and with
+    /// Go-Back-N every frame after it as well, since the receiver discards them.
+    /// A NAK echoing a different copy bit than the last copy sent refers to an
+    /// earlier copy and is ignored.
+    fn fast_retransmit(&mut self, session: &Session, nak: &Payload) -> Result<(), NetworkError> {
+        let offset = nak.id.wrapping_sub(self.base()) as usize;
+        let earlier_copy = |frame: &Outstanding| {
+            (frame.payload.flag & Flags::COPY) != (nak.flag & Flags::COPY)
+        };
+        if self
+            .frames
+            .get(offset)
+            .is_none_or(|frame| !frame.in_flight() || earlier_copy(frame))
+        {
+            println!("STALE NAK {}", nak);
+            return Ok(());
+        }
+        println!("RECV NAK {}", nak);
+        self.frames[offset].retry(session.config().max_send_attempts)?;
+        session.congestion().on_loss(self.next_id);
+
+        let end = match self.arq {
+            Arq::SelectiveRepeat => offset + 1,
+            _ => self.frames.len(),
+        };
+
+        for frame in self.frames.range_mut(offset..end).filter(|frame| !frame.acked) {
+            println!(
+                "({}) FAST RETRANSMISSION of frame {}",
+                frame.transmissions - 1,
+                frame.payload.id
+            );
+            frame.queued = true;
+        }
+
+        Ok(())
+    }
+
+    /// Handles the next ACK or NAK or, once a frame times out, queues
+    /// retransmissions as the scheme requires.
+    async fn await_ack(&mut self, session: &Session) -> Result<(), NetworkError> {
+        let rto = session.rto().rto();
+        let Some(deadline) = self.deadline(rto) else {
+            return Ok(());
+        };
+
+        match communication::next_ack(session, deadline).await {
+            Ok(nak) if nak.flag.contains(Flags::NAK) => self.fast_retransmit(session, &nak),
+            Ok(ack) => {
+                self.acknowledge(session, &ack);
+                Ok(())
+            }
+            Err(e) if e.kind == NetworkErrorKind::TimeoutError => {
+                {
+                    let mut rto = session.rto();
+                    rto.back_off();
+                    println!("RTO BACKOFF {} ms", rto.rto().as_millis());
+                }
+                session.congestion().on_timeout(self.next_id);
+                self.retransmit(rto, session.config().max_send_attempts)
+            }
+            Err(e) if e.kind == NetworkErrorKind::UnexpectedFlagError => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Sends everything in `input` with a windowed [`Arq`] scheme, followed by END
+/// once every frame has been acknowledged.
+pub async fn send_windowed(
+    session: &Session,
+    input: &mut impl Read,
+    mtu: usize,
+) -> std::io::Result<()> {
+    let config = session.config();
+    let mut window = SendWindow::new(&config.format, config.window);
+    let mut eof = false;
+
+    loop {
+        let cwnd = session.congestion().window();
+        if let Err(e) = window.resend_queued(session, cwnd).await {
+            println!("Stop sending: {}", e);
+            return Ok(());
+        }
+
+        while !eof && window.is_open(session.congestion().window()) {
+            let mut read_buf = vec![0u8; mtu];
+            let bytes_read = input.read(&mut read_buf)?;
+            if bytes_read == 0 {
+                eof = true;
+                break;
+            }
+
+            read_buf.truncate(bytes_read);
+            if let Err(e) = window.send(session, read_buf).await {
+                println!("Stop sending: {}", e);
+                return Ok(());
+            }
+        }
+
+        if window.frames.is_empty() {
+            communication::send_end(session, window.next_id).await;
+            return Ok(());
+        }
+
+        if let Err(e) = window.await_ack(session).await {
+            println!("Stop sending: {}", e);
+            return Ok(());
+        }
+    }
+}
+
+/// Receiving side of an [`Arq`] scheme, handing out data frames in order.
+pub enum Receiver {
+    StopAndWait {
+        expected: u16,
+    },
+    GoBackN {
+        expected: u16,
+    },
+    SelectiveRepeat {
+        expected: u16,
+        window: usize,
+        /// Frames received ahead of `expected`, indexed by their distance to it.
+        buffer: VecDeque<Option<Payload>>,
+    },
+}
+
+impl Receiver {
+    pub fn new(config: &DccnetConfig) -> Self {
+        let expected = network::START_ID;
+
+        match config.format.arq {
+            Arq::StopAndWait => Receiver::StopAndWait { expected },
+            Arq::GoBackN => Receiver::GoBackN { expected },
+            Arq::SelectiveRepeat => Receiver::SelectiveRepeat {
+                expected,
+                window: config.window,
+                buffer: VecDeque::with_capacity(config.window),
+            },
+        }
+    }
+
+    /// Returns the next data frame in order, or END. Duplicate and out of order
+    /// frames are acknowledged as the scheme requires and skipped or buffered.
+    pub async fn receive(&mut self, session: &Session) -> Result<Payload, NetworkError> {
+        loop {
+            match self {
+                Receiver::StopAndWait { expected } => {
+                    let payload = communication::receive_frame(session).await?;
+                    if payload.flag.contains(Flags::END) {
+                        return Ok(payload);
+                    }
+
+                    if payload.id == *expected {
+                        *expected = communication::next_id(*expected);
+                        return Ok(payload);
+                    }
+                }
+                Receiver::GoBackN { expected } => {
+                    let payload = match communication::read_data_frame(session).await? {
+                        Received::Frame(payload) => payload,
+                        // Any later frame would be discarded anyway
+                        Received::Corrupted { id, copy } => {
+                            if id == *expected {
+                                communication::send_nak(session, id, copy).await;
+                            }
+                            continue;
+                        }
+                    };
+                    if payload.flag.contains(Flags::END) {
+                        return Ok(payload);
+                    }
+
+                    if payload.id == *expected {
+                        communication::send_ack(session, payload.id).await;
+                        *expected = expected.wrapping_add(1);
+                        return Ok(payload);
+                    }
+
+                    // Repeat the cumulative ACK of the last frame received in order
+                    println!("OUT OF ORDER {} (expected {})", payload.id, expected);
+                    communication::send_ack(session, expected.wrapping_sub(1)).await;
+                }
+                Receiver::SelectiveRepeat {
+                    expected,
+                    window,
+                    buffer,
+                } => {
+                    if let Some(slot) = buffer.front_mut()
+                        && let Some(payload) = slot.take()
+                    {
+                        buffer.pop_front();
+                        *expected = expected.wrapping_add(1);
+                        return Ok(payload);
+                    }
+
+                    let payload = match communication::read_data_frame(session).await? {
+                        Received::Frame(payload) => payload,
+                        Received::Corrupted { id, copy } => {
+                            let offset = id.wrapping_sub(*expected) as usize;
+                            if offset < *window && buffer.get(offset).is_none_or(Option::is_none) {
+                                communication::send_nak(session, id, copy).await;
+                            }
+                            continue;
+                        }
+                    };
+                    if payload.flag.contains(Flags::END) {
+                        return Ok(payload);
+                    }
+
+                    let offset = payload.id.wrapping_sub(*expected) as usize;
+
+                    if offset < *window {
+                        communication::send_ack(session, payload.id).await;
+                        if offset >= buffer.len() {
+                            buffer.resize(offset + 1, None);
+                        }
+                        if offset > 0 {
+                            println!("OUT OF ORDER {} (expected {})", payload.id, expected);
+                        }
+                        buffer[offset].get_or_insert(payload);
+                    } else if expected.wrapping_sub(payload.id) as usize <= *window {
+                        // Already delivered, but its ACK may have been lost
+                        println!("DUPLICATE {}", payload.id);
+                        communication::send_ack(session, payload.id).await;
+                    } else {
+                        println!("OUT OF WINDOW {} (expected {})", payload.id, expected);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// LINKTYPE_USER0: frames start directly with the DCCNET SYNC pattern.
+pub const LINKTYPE_DCCNET: u16 = 147;
+
+const BLOCK_SECTION_HEADER: u32 = 0x0A0D0D0A;
+const BLOCK_INTERFACE_DESCRIPTION: u32 = 0x00000001;
+const BLOCK_ENHANCED_PACKET: u32 = 0x00000006;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B3C4D;
+const OPTION_END: u16 = 0;
+const OPTION_EPB_FLAGS: u16 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+impl Direction {
+    /// Direction bits of the pcapng `epb_flags` option.
+    fn epb_flags(&self) -> u32 {
+        match self {
+            Direction::Received => 0b01,
+            Direction::Sent => 0b10,
+        }
+    }
+}
+
+/// Writes every frame of a connection to a pcapng file that Wireshark can open
+/// with a user DLT, or that scripts can walk block by block.
+pub struct Capture {
+    writer: Mutex<BufWriter<File>>,
+}
+
+fn write_block(writer: &mut impl Write, block_type: u32, body: &[u8]) -> io::Result<()> {
+    let total_len = (12 + body.len()) as u32;
+
+    writer.write_all(&block_type.to_le_bytes())?;
+    writer.write_all(&total_len.to_le_bytes())?;
+    writer.write_all(body)?;
+    writer.write_all(&total_len.to_le_bytes())
+}
+
+impl Capture {
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        let mut section = Vec::new();
+        section.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+        section.extend_from_slice(&1u16.to_le_bytes());
+        section.extend_from_slice(&0u16.to_le_bytes());
+        section.extend_from_slice(&(-1i64).to_le_bytes());
+        write_block(&mut writer, BLOCK_SECTION_HEADER, &section)?;
+
+        let mut interface = Vec::new();
+        interface.extend_from_slice(&LINKTYPE_DCCNET.to_le_bytes());
+        interface.extend_from_slice(&0u16.to_le_bytes());
+        interface.extend_from_slice(&0u32.to_le_bytes());
+        write_block(&mut writer, BLOCK_INTERFACE_DESCRIPTION, &interface)?;
+
+        writer.flush()?;
+        Ok(Self {
+            writer: Mutex::new(writer),
+        })
+    }
+
+    /// Appends one frame, timestamped now with microsecond resolution.
+    pub fn record(&self, direction: Direction, frame: &[u8]) -> io::Result<()> {
+        let micros = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+
+        let mut packet = Vec::with_capacity(frame.len() + 40);
+        packet.extend_from_slice(&0u32.to_le_bytes());
+        packet.extend_from_slice(&((micros >> 32) as u32).to_le_bytes());
+        packet.extend_from_slice(&(micros as u32).to_le_bytes());
+        packet.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        packet.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        packet.extend_from_slice(frame);
+        packet.resize(packet.len().next_multiple_of(4), 0);
+
+        packet.extend_from_slice(&OPTION_EPB_FLAGS.to_le_bytes());
+        packet.extend_from_slice(&4u16.to_le_bytes());
+        packet.extend_from_slice(&direction.epb_flags().to_le_bytes());
+        packet.extend_from_slice(&OPTION_END.to_le_bytes());
+        packet.extend_from_slice(&0u16.to_le_bytes());
+
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        write_block(&mut *writer, BLOCK_ENHANCED_PACKET, &packet)?;
+        writer.flush()
+    }
+}
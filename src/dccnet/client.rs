@@ -3,18 +3,20 @@ use std::io::{BufReader, BufWriter, Error, ErrorKind};
 
 use tokio::net::TcpStream;
 
+use crate::dccnet::config::DccnetConfig;
 use crate::dccnet::xfer;
 
 pub async fn run_client<A: tokio::net::ToSocketAddrs>(
     addr: A,
     mut input: BufReader<File>,
     mut output: BufWriter<File>,
+    config: &DccnetConfig,
 ) -> std::io::Result<()> {
     println!("Connecting to server");
 
-    use tokio::time::{Duration, timeout};
+    use tokio::time::timeout;
 
-    let stream = match timeout(Duration::from_secs(3), TcpStream::connect(addr)).await {
+    let stream = match timeout(config.connect_timeout, TcpStream::connect(addr)).await {
         Ok(Ok(stream)) => stream,
         Ok(Err(e)) => {
             return Err(e);
@@ -24,8 +26,8 @@ pub async fn run_client<A: tokio::net::ToSocketAddrs>(
         }
     };
 
-    xfer::handle_connection(stream, &mut input, &mut output).await;
+    let state = xfer::handle_connection(stream, &mut input, &mut output, config).await;
 
     println!("End connection with server!");
-    Ok(())
+    xfer::check_closed(state)
 }
@@ -0,0 +1,98 @@
+use std::io;
+
+use bytes::{Buf, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use super::{
+    decoder::{self, DecodeStep},
+    network::{FrameFormat, Payload, PayloadError, PayloadErrorKind},
+};
+
+/// Frames DCCNET payloads over any byte stream, e.g. `Framed<TcpStream, DccnetCodec>`.
+///
+/// A candidate frame that fails validation is yielded as an `Err` item after its
+/// SYNC has been skipped, so the stream resumes at the following candidate; only
+/// I/O errors end it.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DccnetCodec {
+    format: FrameFormat,
+    corrected: usize,
+}
+
+impl DccnetCodec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_format(format: FrameFormat) -> Self {
+        Self {
+            format,
+            corrected: 0,
+        }
+    }
+
+    /// Data bytes repaired by FEC in the frames decoded so far.
+    pub fn corrected(&self) -> usize {
+        self.corrected
+    }
+}
+
+impl Decoder for DccnetCodec {
+    type Item = Result<Payload, PayloadError>;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, io::Error> {
+        loop {
+            match decoder::decode_step(src, &self.format) {
+                DecodeStep::NeedMore => return Ok(None),
+                DecodeStep::Discard(n) => src.advance(n),
+                DecodeStep::Frame {
+                    payload,
+                    len,
+                    corrected,
+                } => {
+                    src.advance(len);
+                    self.corrected += corrected;
+                    return Ok(Some(Ok(payload)));
+                }
+                DecodeStep::Invalid { error, skip, .. } => {
+                    src.advance(skip);
+                    return Ok(Some(Err(error)));
+                }
+            }
+        }
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, io::Error> {
+        if let Some(item) = self.decode(src)? {
+            return Ok(Some(item));
+        }
+
+        if src.is_empty() {
+            return Ok(None);
+        }
+
+        let remaining = src.len();
+        src.clear();
+        Ok(Some(Err(PayloadError::new(
+            PayloadErrorKind::InvalidPayload,
+            &format!("Stream ended with {} bytes of incomplete frame", remaining),
+        ))))
+    }
+}
+
+impl Encoder<Payload> for DccnetCodec {
+    type Error = PayloadError;
+
+    fn encode(&mut self, item: Payload, dst: &mut BytesMut) -> Result<(), PayloadError> {
+        if item.data.len() > self.format.mtu {
+            return Err(PayloadError::new(
+                PayloadErrorKind::InvalidData,
+                &format!("Data length {} exceeds MTU of {}", item.data.len(), self.format.mtu),
+            ));
+        }
+
+        dst.extend_from_slice(&item.as_bytes_with(&self.format));
+        Ok(())
+    }
+}
@@ -1,10 +1,22 @@
-use std::fmt;
+use std::{fmt, sync::Arc};
 
-use crate::dccnet::sync_read;
+use crate::dccnet::sync_read::{self, Inbox, Received};
 
-use super::network;
-use super::network::Payload;
-use tokio::{io::AsyncWriteExt, net::tcp::OwnedWriteHalf, sync::Mutex};
+use super::ack::{AckPolicy, PendingAck};
+use super::arq::Arq;
+use super::capture::{Capture, Direction};
+use super::impairment::BitErrors;
+use super::network::{self, Flags, FrameFormat, Payload};
+use super::options::FrameOption;
+use super::piggyback;
+use super::session::Session;
+use super::state::{ConnectionEvent, ConnectionState, TransitionError};
+use tokio::{
+    io::AsyncWriteExt,
+    net::tcp::OwnedWriteHalf,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
 
 #[derive(Debug, PartialEq)]
 #[allow(dead_code)]
@@ -12,11 +24,13 @@ pub enum NetworkErrorKind {
     ConnectionError,
     ProtocolError,
     RSTError,
+    NAKError,
     UnexpectedFlagError,
     RetransmissionError,
     InvalidIdError,
     TimeoutError,
     ConnectionClosed,
+    StateError,
     Other,
 }
 
@@ -46,13 +60,95 @@ impl fmt::Display for NetworkError {
     }
 }
 
+impl From<TransitionError> for NetworkError {
+    fn from(e: TransitionError) -> Self {
+        NetworkError::new(NetworkErrorKind::StateError, &e.to_string())
+    }
+}
+
+/// Write half of a connection together with the frame format it speaks.
+pub struct FrameWriter {
+    stream: Mutex<OwnedWriteHalf>,
+    format: FrameFormat,
+    capture: Option<Arc<Capture>>,
+    bit_errors: Option<BitErrors>,
+    last_write: std::sync::Mutex<Instant>,
+}
+
+impl FrameWriter {
+    pub fn new(
+        stream: OwnedWriteHalf,
+        format: FrameFormat,
+        capture: Option<Arc<Capture>>,
+        bit_errors: Option<BitErrors>,
+    ) -> Self {
+        Self {
+            stream: Mutex::new(stream),
+            format,
+            capture,
+            bit_errors,
+            last_write: std::sync::Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn format(&self) -> &FrameFormat {
+        &self.format
+    }
+
+    pub fn capture(&self) -> Option<&Arc<Capture>> {
+        self.capture.as_ref()
+    }
+
+    /// Bit errors injected into the outgoing bytes, if enabled.
+    pub fn bit_errors(&self) -> Option<&BitErrors> {
+        self.bit_errors.as_ref()
+    }
+
+    /// When a frame was last written, or the writer was created.
+    pub fn last_write(&self) -> Instant {
+        *self.last_write.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn payload(&self, data: Vec<u8>, id: u16, flag: Flags) -> Payload {
+        Payload::with_format(data, id, flag, &self.format)
+    }
+
+    pub async fn write_payload(&self, payload: &Payload) -> std::io::Result<()> {
+        let mut bytes = payload.as_bytes_with(&self.format);
+        let mut stream = self.stream.lock().await;
+        // Under the stream lock, so the errors follow the order bytes go out in
+        if let Some(bit_errors) = &self.bit_errors {
+            bit_errors.apply(&mut bytes);
+        }
+        stream.write_all(&bytes).await?;
+        drop(stream);
+        *self.last_write.lock().unwrap_or_else(|e| e.into_inner()) = Instant::now();
+
+        if let Some(capture) = &self.capture {
+            capture.record(Direction::Sent, &bytes).unwrap_or_else(|e| {
+                eprintln!("Failed to capture frame: {}", e);
+            });
+        }
+
+        Ok(())
+    }
+
+    pub async fn shutdown(&self) -> std::io::Result<()> {
+        self.stream.lock().await.shutdown().await
+    }
+}
+
 #[inline(always)]
 pub fn next_id(id: u16) -> u16 {
     (id + 1) % 2
 }
 
+pub fn is_rst(payload: &Payload) -> bool {
+    payload.flag.contains(Flags::RST) && payload.id == u16::MAX
+}
+
 fn check_received_rst(payload: &Payload) -> Result<(), NetworkError> {
-    if payload.flag == network::FLAG_RST && payload.id == u16::MAX {
+    if is_rst(payload) {
         let payload_msg = String::from_utf8(payload.data.clone())
             .unwrap_or_else(|_| String::from("Invalid UTF-8 sequence"));
 
@@ -65,26 +161,80 @@ fn check_received_rst(payload: &Payload) -> Result<(), NetworkError> {
     Ok(())
 }
 
-pub async fn send_frame(
-    stream_white: &Mutex<OwnedWriteHalf>,
-    payload: &Payload,
-) -> Result<usize, NetworkError> {
-    for curr_attempt in 0..network::MAX_SEND_ATTEMPTS {
-        if let Err(e) = stream_white
-            .lock()
-            .await
-            .write_all(&payload.as_bytes())
-            .await
-        {
-            return Err(NetworkError::new(
-                NetworkErrorKind::ConnectionError,
-                &format!("Failed to send frame: {}", e),
-            ));
-        }
-        println!("SEND     {payload}");
+/// Fails unless the connection is in a state that `allows` the operation.
+fn check_state(
+    session: &Session,
+    allows: fn(ConnectionState) -> bool,
+    operation: &str,
+) -> Result<(), NetworkError> {
+    match session.state().state() {
+        ConnectionState::Reset => Err(reset_error()),
+        ConnectionState::Aborted => Err(closed_error()),
+        state if !allows(state) => Err(NetworkError::new(
+            NetworkErrorKind::StateError,
+            &format!("Cannot {} in state {}", operation, state),
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn reset_error() -> NetworkError {
+    NetworkError::new(NetworkErrorKind::RSTError, "Connection reset")
+}
+
+pub fn closed_error() -> NetworkError {
+    NetworkError::new(NetworkErrorKind::ConnectionClosed, "Connection closed by peer")
+}
+
+/// Aborts the connection once the stream ended before it was closed cleanly,
+/// which a read notices after taking every frame received before.
+fn peer_lost(session: &Session) -> NetworkError {
+    if !session.state().state().is_final() {
+        let _ = session.state().apply(ConnectionEvent::PeerLost);
+    }
+    closed_error()
+}
+
+/// Transmits a data frame once, without waiting for its ACK. A pending ACK
+/// rides on it when piggybacking is enabled.
+pub async fn write_frame(session: &Session, payload: &Payload) -> Result<(), NetworkError> {
+    check_state(session, ConnectionState::can_send, "send")?;
+
+    let format = session.writer().format();
+    let piggybacked = if format.piggyback && piggyback::fits(payload, format) {
+        session
+            .pending_ack()
+            .take()
+            .map(|ack_id| piggyback::attach(payload, ack_id, format))
+    } else {
+        None
+    };
+    let payload = piggybacked.as_ref().unwrap_or(payload);
+
+    if let Err(e) = session.writer().write_payload(payload).await {
+        return Err(NetworkError::new(
+            NetworkErrorKind::ConnectionError,
+            &format!("Failed to send frame: {}", e),
+        ));
+    }
+    println!("SEND     {payload}");
+    if piggybacked.is_some() {
+        session.pending_ack().record_sent(true);
+    }
 
-        match wait_ack(payload.id).await {
+    Ok(())
+}
+
+pub async fn send_frame(session: &Session, payload: &Payload) -> Result<usize, NetworkError> {
+    for curr_attempt in 0..session.config().max_send_attempts {
+        write_frame(session, payload).await?;
+        let sent_at = Instant::now();
+        let rto = session.rto().rto();
+
+        match wait_ack(session, payload.id, rto).await {
             Ok(_) => {
+                // Karn's rule: the ACK of a retransmission may answer any of the copies
+                session.rto().acknowledged(sent_at.elapsed(), curr_attempt + 1);
                 if curr_attempt > 0 {
                     println!("SUCCESS RETRANSMISSION");
                 }
@@ -95,9 +245,23 @@ pub async fn send_frame(
                     return Err(e);
                 }
 
-                if e.kind == NetworkErrorKind::ConnectionError {
+                if e.kind == NetworkErrorKind::ConnectionError
+                    || e.kind == NetworkErrorKind::ConnectionClosed
+                {
                     return Err(e);
                 }
+
+                // Lost for sure rather than late, so the timeout is left alone
+                if e.kind == NetworkErrorKind::NAKError {
+                    println!("({curr_attempt}) FAST RETRANSMISSION");
+                    continue;
+                }
+
+                if e.kind == NetworkErrorKind::TimeoutError {
+                    let mut rto = session.rto();
+                    rto.back_off();
+                    println!("RTO BACKOFF {} ms", rto.rto().as_millis());
+                }
             }
         }
         println!("({curr_attempt}) RETRANSMISSION");
@@ -109,12 +273,41 @@ pub async fn send_frame(
     ))
 }
 
-pub async fn receive_frame(stream_white: &Mutex<OwnedWriteHalf>) -> Result<Payload, NetworkError> {
-    let payload = sync_read::read_stream_data().await?;
+/// Waits for the next data frame, including END. Only END is acknowledged here,
+/// and only with END-ACK enabled.
+/// A frame that arrived corrupted shows up as `Received::Corrupted`, for the
+/// caller to answer with a NAK. Fails with `ConnectionClosed` once the stream
+/// ended and every frame received before was read.
+pub async fn read_data_frame(session: &Session) -> Result<Received, NetworkError> {
+    check_state(session, ConnectionState::can_receive, "receive")?;
+
+    let payload = loop {
+        // Pending frames go first so the message of a received RST is not lost
+        let received = tokio::select! {
+            biased;
+            received = sync_read::read_stream_data(session.inbox()) => {
+                received.ok_or_else(|| peer_lost(session))?
+            }
+            _ = session.state().reset() => return Err(reset_error()),
+        };
+
+        match received {
+            Received::Frame(payload) if negotiated_mtu(&payload).is_some() => {
+                println!("RECV OPT {payload}");
+                let mtu = session.writer().format().mtu;
+                send_mtu(session.writer(), mtu, Flags::ACK | Flags::OPT).await?;
+            }
+            Received::Frame(payload) => break payload,
+            Received::Corrupted { id, .. } => {
+                println!("CORRUPTED {}", id);
+                return Ok(received);
+            }
+        }
+    };
 
     check_received_rst(&payload)?;
 
-    if payload.flag == network::FLAG_ACK {
+    if payload.flag == Flags::ACK {
         return Err(NetworkError::new(
             NetworkErrorKind::UnexpectedFlagError,
             "Received ACK instead of data",
@@ -122,75 +315,374 @@ pub async fn receive_frame(stream_white: &Mutex<OwnedWriteHalf>) -> Result<Paylo
     }
 
     println!("RECV \t {}", payload);
-    if payload.flag == network::FLAG_END {
-        return Ok(payload);
+    if payload.flag.contains(Flags::END) {
+        if session.writer().format().end_ack {
+            send_ack(session, payload.id).await;
+        }
+        session.state().apply(ConnectionEvent::RecvEnd)?;
     }
 
-    send_ack(stream_white, payload.id).await;
-    Ok(payload)
+    Ok(Received::Frame(payload))
 }
 
-async fn wait_ack(id: u16) -> Result<Payload, NetworkError> {
-    let payload = sync_read::read_stream_ack().await?;
+/// Waits for the next data frame, including END, and acknowledges it. A frame
+/// that arrived corrupted is answered with a NAK, which a stop-and-wait sender
+/// ignores unless it refers to the frame it is sending.
+pub async fn receive_frame(session: &Session) -> Result<Payload, NetworkError> {
+    loop {
+        let payload = match read_data_frame(session).await? {
+            Received::Frame(payload) => payload,
+            Received::Corrupted { id, copy } => {
+                send_nak(session, id, copy).await;
+                continue;
+            }
+        };
+
+        // END was acknowledged when it was read
+        if !payload.flag.contains(Flags::END) {
+            send_ack(session, payload.id).await;
+        }
+        return Ok(payload);
+    }
+}
+
+/// Waits until `deadline` for the next ACK or NAK, whatever frame it refers to.
+pub async fn next_ack(session: &Session, deadline: Instant) -> Result<Payload, NetworkError> {
+    // The sender has nothing to carry ACKs on while it waits
+    let carrier = session.pending_ack().carrier();
+    set_carrier(session, false).await;
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    let payload = tokio::select! {
+        biased;
+        payload = sync_read::read_stream_ack(session.inbox(), remaining) => payload,
+        _ = session.state().reset() => Err(reset_error()),
+    };
+    session.pending_ack().set_carrier(carrier);
+    let payload = match payload {
+        // An END may still wait to be read, so the receiving side aborts the
+        // connection unless it already stopped reading
+        Err(e) if e.kind == NetworkErrorKind::ConnectionClosed => {
+            if session.state().state().can_receive() {
+                return Err(closed_error());
+            }
+            return Err(peer_lost(session));
+        }
+        payload => payload?,
+    };
 
     check_received_rst(&payload)?;
 
-    if payload.flag != network::FLAG_ACK {
+    if !payload.flag.intersects(Flags::ACK | Flags::NAK) {
         return Err(NetworkError::new(
             NetworkErrorKind::UnexpectedFlagError,
             "Received unexpected flag",
         ));
     }
 
-    if payload.id != id {
-        return Err(NetworkError::new(
-            NetworkErrorKind::InvalidIdError,
-            &format!("Received ACK with invalid ID: {}", payload.id),
-        ));
+    Ok(payload)
+}
+
+async fn wait_ack(session: &Session, id: u16, rto: Duration) -> Result<Payload, NetworkError> {
+    let deadline = Instant::now() + rto;
+
+    loop {
+        let payload = next_ack(session, deadline).await?;
+
+        let nak = payload.flag.contains(Flags::NAK);
+
+        // Duplicate ACKs of the previous frame stay queued after a retransmission
+        if payload.id != id {
+            println!("STALE {} {}", if nak { "NAK" } else { "ACK" }, payload);
+            continue;
+        }
+
+        if nak {
+            println!("RECV NAK {}", payload);
+            return Err(NetworkError::new(
+                NetworkErrorKind::NAKError,
+                "Frame arrived corrupted",
+            ));
+        }
+
+        println!("RECV ACK {}", payload);
+        return Ok(payload);
     }
+}
 
-    println!("RECV ACK {}", payload);
-    Ok(payload)
+/// Acknowledges frame `id` as the ACK policy requires. An ACK held back, or
+/// waiting for an outgoing data frame to carry it when piggybacking, is
+/// replaced by the next one, except with Selective Repeat where each ACK
+/// covers only its own frame.
+pub async fn send_ack(session: &Session, id: u16) {
+    let pending_ack = session.pending_ack();
+    let format = session.writer().format();
+    let policy = session.config().ack_policy;
+
+    // Selective Repeat ACKs are not cumulative, so an older one goes out alone
+    if let Some(previous) = pending_ack.replace(id)
+        && previous != id
+        && format.arq == Arq::SelectiveRepeat
+    {
+        write_ack(session.writer(), session.inbox(), pending_ack, previous).await;
+    }
+
+    let queued = match policy {
+        AckPolicy::EveryK(_) | AckPolicy::Cumulative => session.inbox().has_data().await,
+        AckPolicy::Immediate | AckPolicy::Delayed(_) => false,
+    };
+    let delay = policy.delay(pending_ack.held(), queued).or_else(|| {
+        (format.piggyback && pending_ack.carrier()).then_some(session.config().piggyback_delay)
+    });
+
+    let Some(delay) = delay else {
+        if pending_ack.take_if(id) {
+            write_ack(session.writer(), session.inbox(), pending_ack, id).await;
+        }
+        return;
+    };
+
+    let writer = Arc::clone(session.writer());
+    let inbox = Arc::clone(session.inbox());
+    let pending_ack = Arc::clone(pending_ack);
+    tokio::spawn(async move {
+        if pending_ack.take_after(id, delay).await {
+            write_ack(&writer, &inbox, &pending_ack, id).await;
+        }
+    });
 }
 
-async fn send_ack(stream_white: &Mutex<OwnedWriteHalf>, id: u16) {
-    let payload = Payload::new(vec![], id, network::FLAG_ACK);
-    println!("SEND ACK {payload}");
+/// Marks whether data frames may follow soon. Without one, an ACK waiting to
+/// ride on a data frame goes out alone right away, while one held back by the
+/// ACK policy keeps waiting.
+async fn set_carrier(session: &Session, carrier: bool) {
+    session.pending_ack().set_carrier(carrier);
 
-    if let Err(e) = stream_white
-        .lock()
-        .await
-        .write_all(&payload.as_bytes())
-        .await
+    if !carrier
+        && session.config().ack_policy == AckPolicy::Immediate
+        && let Some(id) = session.pending_ack().take()
     {
+        write_ack(session.writer(), session.inbox(), session.pending_ack(), id).await;
+    }
+}
+
+/// Writes the ACK of frame `id`, advertising the room left in the data queue
+/// when flow control is enabled.
+async fn write_ack(writer: &FrameWriter, inbox: &Inbox, pending_ack: &PendingAck, id: u16) {
+    let payload = if writer.format().flow_control {
+        let window = inbox.data_space().await.min(u16::MAX as usize) as u16;
+        let options = FrameOption::encode_all(&[FrameOption::Window(window)]);
+        writer.payload(options, id, Flags::ACK | Flags::OPT)
+    } else {
+        writer.payload(vec![], id, Flags::ACK)
+    };
+    println!("SEND ACK {payload}");
+
+    if let Err(e) = writer.write_payload(&payload).await {
         eprintln!("Failed to send ACK: {}", e);
+        return;
     }
+    pending_ack.record_sent(false);
 }
 
-pub async fn send_rst(stream_white: &Mutex<OwnedWriteHalf>, data: Option<Vec<u8>>) {
-    let payload = Payload::new(data.unwrap_or_default(), u16::MAX, network::FLAG_RST);
+/// Answers a frame that arrived corrupted, asking the peer to resend it and
+/// echoing its copy bit.
+pub async fn send_nak(session: &Session, id: u16, copy: Flags) {
+    let payload = session.writer().payload(vec![], id, Flags::NAK | copy);
+    println!("SEND NAK {payload}");
+
+    if let Err(e) = session.writer().write_payload(&payload).await {
+        eprintln!("Failed to send NAK: {}", e);
+    }
+}
+
+pub async fn send_rst(session: &Session, data: Option<Vec<u8>>) {
+    let payload = session.writer().payload(data.unwrap_or_default(), u16::MAX, Flags::RST);
     println!("SEND RST {payload}");
 
-    if let Err(e) = stream_white
-        .lock()
-        .await
-        .write_all(&payload.as_bytes())
-        .await
-    {
+    if let Err(e) = session.writer().write_payload(&payload).await {
         eprintln!("Failed to send RST: {}", e);
     }
+    let _ = session.state().apply(ConnectionEvent::Rst);
 }
 
-pub async fn send_end(stream_white: &Mutex<OwnedWriteHalf>, id: u16) {
-    let payload = Payload::new(vec![], id, network::FLAG_END);
-    println!("SEND END {payload}");
+/// Sends END once, as the specification does, or with END-ACK enabled
+/// retransmits it like a data frame until it is acknowledged.
+pub async fn send_end(session: &Session, id: u16) {
+    let payload = session.writer().payload(vec![], id, Flags::END);
+    set_carrier(session, false).await;
 
-    if let Err(e) = stream_white
-        .lock()
-        .await
-        .write_all(&payload.as_bytes())
-        .await
-    {
+    if let Err(e) = write_frame(session, &payload).await {
         eprintln!("Failed to send END: {}", e);
+        return;
+    }
+
+    // END counts as sent right away, so a peer that closes once both ENDs went
+    // through, without acknowledging this one, still closes cleanly
+    if session.state().apply(ConnectionEvent::SendEnd).is_err()
+        || !session.writer().format().end_ack
+    {
+        return;
+    }
+
+    if let Err(e) = await_end_ack(session, &payload).await {
+        eprintln!("END not acknowledged: {}", e);
+    }
+}
+
+/// Retransmits END until it is acknowledged or the connection is over.
+async fn await_end_ack(session: &Session, payload: &Payload) -> Result<(), NetworkError> {
+    for attempt in 1.. {
+        let rto = session.rto().rto();
+        match wait_ack(session, payload.id, rto).await {
+            Ok(_) => return Ok(()),
+            Err(e) if e.kind == NetworkErrorKind::TimeoutError => {
+                let mut rto = session.rto();
+                rto.back_off();
+                println!("RTO BACKOFF {} ms", rto.rto().as_millis());
+            }
+            Err(e) if e.kind == NetworkErrorKind::NAKError => {}
+            // The peer may close as soon as both ENDs went through, which the
+            // receiving side tells once it read every frame before the close
+            Err(e) if e.kind == NetworkErrorKind::ConnectionClosed => return Ok(()),
+            Err(e) => return Err(e),
+        }
+
+        if attempt >= session.config().max_send_attempts {
+            break;
+        }
+        println!("({attempt}) RETRANSMISSION");
+        if let Err(e) = session.writer().write_payload(payload).await {
+            return Err(NetworkError::new(
+                NetworkErrorKind::ConnectionError,
+                &format!("Failed to send frame: {}", e),
+            ));
+        }
+        println!("SEND     {payload}");
+    }
+
+    Err(NetworkError::new(
+        NetworkErrorKind::RetransmissionError,
+        "Failed to send frame after maximum attempts",
+    ))
+}
+
+/// Acknowledges the END frames the peer retransmits after its END was received,
+/// in case the ACK got lost. Returns once the stream ends or is reset.
+pub async fn answer_end(session: &Session) {
+    loop {
+        let received = tokio::select! {
+            received = sync_read::read_stream_data(session.inbox()) => received,
+            _ = session.state().reset() => return,
+        };
+        let Some(received) = received else {
+            return;
+        };
+        let Received::Frame(payload) = received else {
+            continue;
+        };
+
+        if payload.flag.contains(Flags::END) {
+            println!("RECV \t {}", payload);
+            send_ack(session, payload.id).await;
+        }
+    }
+}
+
+/// Id of the OPT frames of the MTU negotiation. No stop-and-wait data frame
+/// uses it, so a peer without negotiation takes them for old copies and their
+/// ACKs are not mistaken for those of data frames.
+const NEGOTIATION_ID: u16 = u16::MAX;
+
+/// The MTU carried by an OPT frame of the MTU negotiation, if it is one.
+pub fn negotiated_mtu(payload: &Payload) -> Option<usize> {
+    if !payload.flag.contains(Flags::OPT) {
+        return None;
     }
+
+    FrameOption::decode_all(&payload.data)
+        .into_iter()
+        .find_map(|option| match option {
+            FrameOption::Mtu(mtu) => Some(mtu as usize),
+            _ => None,
+        })
+}
+
+/// Sends this end's MTU in an OPT frame, or in an ACK|OPT frame when answering
+/// the OPT of the peer.
+pub async fn send_mtu(writer: &FrameWriter, mtu: usize, flag: Flags) -> Result<(), NetworkError> {
+    let options = FrameOption::encode_all(&[FrameOption::Mtu(mtu as u16)]);
+    let payload = writer.payload(options, NEGOTIATION_ID, flag);
+    println!("SEND OPT {payload}");
+
+    writer.write_payload(&payload).await.map_err(|e| {
+        NetworkError::new(
+            NetworkErrorKind::ConnectionError,
+            &format!("Failed to send OPT: {}", e),
+        )
+    })
+}
+
+/// Exchanges OPT frames carrying each side's MTU before any data is sent and
+/// returns the smaller of the two. Each end retransmits its OPT until the peer
+/// answers it with ACK|OPT, and answers every OPT it receives that way.
+/// A peer that sends anything else first does not negotiate, so the smaller of
+/// the local MTU and the specification maximum is used instead. Must run
+/// before the reader task of the session is started.
+pub async fn negotiate_mtu(session: &mut Session) -> Result<usize, NetworkError> {
+    let local_mtu = session.writer().format().mtu;
+    let mut peer_mtu = None;
+    send_mtu(session.writer(), local_mtu, Flags::OPT).await?;
+
+    for attempt in 1.. {
+        let deadline = Instant::now() + session.rto().rto();
+        loop {
+            let payload = match tokio::time::timeout_at(deadline, session.read_payload()).await {
+                Ok(Ok(payload)) => payload,
+                Ok(Err(e)) if e.kind == NetworkErrorKind::ProtocolError => continue,
+                Ok(Err(e)) => return Err(e),
+                Err(_) => break,
+            };
+
+            check_received_rst(&payload)?;
+
+            let Some(mtu) = negotiated_mtu(&payload) else {
+                // Any other frame comes from a peer done negotiating, or from one
+                // that does not negotiate. An ACK can only be for the OPT
+                if !payload.flag.contains(Flags::ACK) {
+                    session.unread(payload);
+                }
+                return Ok(match peer_mtu {
+                    Some(peer_mtu) => local_mtu.min(peer_mtu),
+                    None => {
+                        println!("Peer does not negotiate MTU");
+                        local_mtu.min(network::MAX_DATA_SIZE)
+                    }
+                });
+            };
+            println!("RECV OPT {payload}");
+
+            if payload.flag.contains(Flags::ACK) {
+                return Ok(local_mtu.min(mtu));
+            }
+            peer_mtu = Some(mtu);
+            send_mtu(session.writer(), local_mtu, Flags::ACK | Flags::OPT).await?;
+        }
+
+        {
+            let mut rto = session.rto();
+            rto.back_off();
+            println!("RTO BACKOFF {} ms", rto.rto().as_millis());
+        }
+
+        if attempt >= session.config().max_send_attempts {
+            break;
+        }
+        println!("({attempt}) RETRANSMISSION");
+        send_mtu(session.writer(), local_mtu, Flags::OPT).await?;
+    }
+
+    // The peer told its MTU but none of the answers to this end's OPT came
+    // through, or it stayed silent
+    println!("OPT not answered");
+    Ok(local_mtu.min(peer_mtu.unwrap_or(network::MAX_DATA_SIZE)))
 }
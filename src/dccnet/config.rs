@@ -0,0 +1,178 @@
+use std::{sync::Arc, time::Duration};
+
+use super::{
+    ack::AckPolicy,
+    arq,
+    capture::Capture,
+    congestion::Congestion,
+    impairment::{self, Impairment},
+    network::FrameFormat,
+};
+
+pub const DEFAULT_ACK_TIMEOUT: Duration = Duration::from_secs(1);
+pub const DEFAULT_MIN_RTO: Duration = Duration::from_millis(20);
+pub const DEFAULT_MAX_RTO: Duration = Duration::from_secs(10);
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(3);
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+pub const DEFAULT_LINGER: Duration = Duration::from_secs(1);
+pub const DEFAULT_DEAD_PEER_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_PIGGYBACK_DELAY: Duration = Duration::from_millis(10);
+pub const DEFAULT_MAX_SEND_ATTEMPTS: usize = 16;
+pub const DEFAULT_QUEUE_DEPTH: usize = 32;
+pub const DEFAULT_WINDOW: usize = 16;
+
+/// Everything that tunes a connection: the wire format both ends agree on plus
+/// the local timeouts, retry limits and buffering.
+#[derive(Clone)]
+pub struct DccnetConfig {
+    pub format: FrameFormat,
+    /// How long to wait for the ACK of a frame before retransmitting it, until
+    /// round-trip samples provide an adaptive timeout.
+    pub ack_timeout: Duration,
+    /// Bounds of the adaptive retransmission timeout. The upper one is further
+    /// limited to half of the silence timeout.
+    pub min_rto: Duration,
+    pub max_rto: Duration,
+    /// How long the peer may stay silent before the connection is considered over.
+    pub read_timeout: Duration,
+    /// Interval of the keepalive frames sent while this end has nothing else
+    /// to send. Disabled by default, as peers without keepalive support would
+    /// take them for data.
+    pub keepalive: Option<Duration>,
+    /// Replaces `read_timeout` when keepalives are enabled, since a silent
+    /// peer is then known to be dead rather than idle.
+    pub dead_peer_timeout: Duration,
+    pub connect_timeout: Duration,
+    /// How long to keep acknowledging a retransmitted END of the peer before
+    /// shutting the connection down.
+    pub linger: Duration,
+    /// Transmissions of a frame, including the first one, before giving up.
+    pub max_send_attempts: usize,
+    /// Received ACK and data frames buffered per queue.
+    pub queue_depth: usize,
+    /// Frames in flight at once when the ARQ mode allows more than one.
+    pub window: usize,
+    /// How the windowed ARQ modes adapt the window, which stays below `window`.
+    pub congestion: Congestion,
+    /// How long an ACK waits for a data frame to ride on before it is sent alone.
+    pub piggyback_delay: Duration,
+    /// When received data frames are acknowledged.
+    pub ack_policy: AckPolicy,
+    /// Bit errors injected into the byte stream, to test error recovery.
+    pub impairment: Impairment,
+    pub capture: Option<Arc<Capture>>,
+}
+
+impl Default for DccnetConfig {
+    fn default() -> Self {
+        Self {
+            format: FrameFormat::default(),
+            ack_timeout: DEFAULT_ACK_TIMEOUT,
+            min_rto: DEFAULT_MIN_RTO,
+            max_rto: DEFAULT_MAX_RTO,
+            read_timeout: DEFAULT_READ_TIMEOUT,
+            keepalive: None,
+            dead_peer_timeout: DEFAULT_DEAD_PEER_TIMEOUT,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            linger: DEFAULT_LINGER,
+            max_send_attempts: DEFAULT_MAX_SEND_ATTEMPTS,
+            queue_depth: DEFAULT_QUEUE_DEPTH,
+            window: DEFAULT_WINDOW,
+            congestion: Congestion::default(),
+            piggyback_delay: DEFAULT_PIGGYBACK_DELAY,
+            ack_policy: AckPolicy::default(),
+            impairment: Impairment::default(),
+            capture: None,
+        }
+    }
+}
+
+fn parse_millis(name: &str, value: &str) -> Result<Duration, String> {
+    match value.parse::<u64>() {
+        Ok(millis) if millis > 0 => Ok(Duration::from_millis(millis)),
+        _ => Err(format!("Invalid value for {}: {} (milliseconds expected)", name, value)),
+    }
+}
+
+fn parse_count(name: &str, value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(format!("Invalid value for {}: {}", name, value)),
+    }
+}
+
+impl DccnetConfig {
+    /// Applies a `--name=value` command-line option that does not affect the
+    /// wire format, so every application accepts the same ones.
+    pub fn parse_option(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name {
+            "--ack-timeout" => self.ack_timeout = parse_millis(name, value)?,
+            "--min-rto" => self.min_rto = parse_millis(name, value)?,
+            "--max-rto" => self.max_rto = parse_millis(name, value)?,
+            "--read-timeout" => self.read_timeout = parse_millis(name, value)?,
+            "--keepalive" => self.keepalive = Some(parse_millis(name, value)?),
+            "--dead-peer-timeout" => self.dead_peer_timeout = parse_millis(name, value)?,
+            "--connect-timeout" => self.connect_timeout = parse_millis(name, value)?,
+            "--linger" => self.linger = parse_millis(name, value)?,
+            "--max-attempts" => self.max_send_attempts = parse_count(name, value)?,
+            "--queue-depth" => self.queue_depth = parse_count(name, value)?,
+            "--window" => {
+                self.window = match parse_count(name, value)? {
+                    window if window <= arq::MAX_WINDOW => window,
+                    _ => return Err(format!("Window must be at most {} frames", arq::MAX_WINDOW)),
+                }
+            }
+            "--congestion" => self.congestion = value.parse()?,
+            "--piggyback-delay" => self.piggyback_delay = parse_millis(name, value)?,
+            "--ack-policy" => self.ack_policy = value.parse()?,
+            "--bit-errors" => self.impairment.outgoing = impairment::parse_rate(name, value)?,
+            "--bit-errors-in" => self.impairment.incoming = impairment::parse_rate(name, value)?,
+            "--bit-error-seed" => {
+                self.impairment.seed = value
+                    .parse()
+                    .map_err(|_| format!("Invalid value for {}: {}", name, value))?
+            }
+            "--pcap" => {
+                let file = Capture::create(value)
+                    .map_err(|e| format!("Failed to create capture file {}: {}", value, e))?;
+                self.capture = Some(Arc::new(file));
+            }
+            _ => return Err(format!("Invalid option: {}", name)),
+        }
+
+        Ok(())
+    }
+
+    /// How long the peer may stay silent before the connection is considered over.
+    pub fn silence_timeout(&self) -> Duration {
+        match self.keepalive {
+            Some(_) => self.dead_peer_timeout,
+            None => self.read_timeout,
+        }
+    }
+
+    /// Usage lines for the options accepted by [`DccnetConfig::parse_option`].
+    pub fn usage() -> &'static str {
+        concat!(
+            "  --ack-timeout=<MS>         Initial wait for an ACK before retransmitting (default: 1000)\n",
+            "  --min-rto=<MS>             Lower bound of the adaptive ACK wait (default: 20)\n",
+            "  --max-rto=<MS>             Upper bound of the adaptive ACK wait (default: 10000)\n",
+            "  --read-timeout=<MS>        Give up on a silent peer (default: 3000)\n",
+            "  --keepalive=<MS>           Send keepalives while idle, expecting them from the peer\n",
+            "  --dead-peer-timeout=<MS>   Give up on a silent peer with keepalives (default: 10000)\n",
+            "  --connect-timeout=<MS>     Give up connecting to the server (default: 3000)\n",
+            "  --linger=<MS>              Answer a retransmitted END before closing (default: 1000)\n",
+            "  --max-attempts=<N>         Transmissions of a frame before giving up (default: 16)\n",
+            "  --queue-depth=<FRAMES>     Received frames buffered per queue (default: 32)\n",
+            "  --window=<FRAMES>          Frames in flight with a windowed ARQ mode (default: 16)\n",
+            "  --congestion=<fixed|aimd>  Congestion control of windowed ARQ modes (default: fixed)\n",
+            "  --piggyback-delay=<MS>     Wait for data to carry an ACK when piggybacking (default: 10)\n",
+            "  --ack-policy=<POLICY>      When to acknowledge data: immediate, delayed:<MS>, every:<K>\n",
+            "                             or cumulative (default: immediate)\n",
+            "  --bit-errors=<RATE>        Flip each outgoing bit with this probability\n",
+            "  --bit-errors-in=<RATE>     Flip each incoming bit with this probability\n",
+            "  --bit-error-seed=<N>       Seed choosing the flipped bits (default: 0)\n",
+            "  --pcap=<FILE>              Capture every frame to a pcapng file",
+        )
+    }
+}
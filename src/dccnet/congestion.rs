@@ -0,0 +1,275 @@
+use std::{fmt, str::FromStr};
+
+/// Decides how many frames a windowed sender may have in flight, from the
+/// ACKs it receives and the losses it detects.
+pub trait CongestionControl: Send {
+    /// Frames that may be outstanding at once.
+    fn window(&self) -> usize;
+
+    /// `frames` were acknowledged for the first time, leaving `base` as the
+    /// oldest frame not yet acknowledged, or the next one to be sent.
+    fn on_ack(&mut self, frames: usize, base: u16);
+
+    /// A frame was reported lost while others still got through, as with a NAK.
+    /// `next_id` is the id of the next new frame.
+    fn on_loss(&mut self, next_id: u16);
+
+    /// Frames went unacknowledged until the retransmission timeout.
+    fn on_timeout(&mut self, next_id: u16);
+}
+
+/// Congestion controller used by the windowed ARQ modes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Congestion {
+    /// Always the configured window.
+    #[default]
+    Fixed,
+    /// Slow start followed by additive increase, halving the window on loss.
+    Aimd,
+}
+
+impl Congestion {
+    /// A controller that never lets more than `max_window` frames out.
+    pub fn controller(self, max_window: usize) -> Box<dyn CongestionControl> {
+        match self {
+            Congestion::Fixed => Box::new(FixedWindow(max_window)),
+            Congestion::Aimd => Box::new(Aimd::new(max_window)),
+        }
+    }
+}
+
+impl FromStr for Congestion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fixed" => Ok(Congestion::Fixed),
+            "aimd" => Ok(Congestion::Aimd),
+            _ => Err(format!("Unknown congestion control: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Congestion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Congestion::Fixed => write!(f, "fixed"),
+            Congestion::Aimd => write!(f, "aimd"),
+        }
+    }
+}
+
+/// Ignores congestion altogether.
+pub struct FixedWindow(usize);
+
+impl CongestionControl for FixedWindow {
+    fn window(&self) -> usize {
+        self.0
+    }
+
+    fn on_ack(&mut self, _frames: usize, _base: u16) {}
+
+    fn on_loss(&mut self, _next_id: u16) {}
+
+    fn on_timeout(&mut self, _next_id: u16) {}
+}
+
+/// Additive increase, multiplicative decrease in the manner of TCP Reno, counted
+/// in frames: the window doubles every round trip during slow start and then
+/// grows by one frame per round trip. A loss halves it, a timeout restarts
+/// slow start from a single frame. Further losses among the frames sent before
+/// the window shrank are part of the same congestion event and are ignored.
+pub struct Aimd {
+    cwnd: usize,
+    ssthresh: usize,
+    max_window: usize,
+    /// Frames acknowledged since the window last grew in congestion avoidance.
+    acked: usize,
+    /// Id of the first frame sent after the window last shrank, until every
+    /// frame before it was acknowledged.
+    recovery: Option<u16>,
+}
+
+impl Aimd {
+    pub fn new(max_window: usize) -> Self {
+        Self {
+            cwnd: 1,
+            ssthresh: max_window,
+            max_window,
+            acked: 0,
+            recovery: None,
+        }
+    }
+
+    fn decrease(&mut self, next_id: u16) {
+        self.ssthresh = (self.cwnd / 2).max(2);
+        self.acked = 0;
+        self.recovery = Some(next_id);
+    }
+}
+
+impl CongestionControl for Aimd {
+    fn window(&self) -> usize {
+        self.cwnd
+    }
+
+    fn on_ack(&mut self, frames: usize, base: u16) {
+        if self
+            .recovery
+            .is_some_and(|recovery| base.wrapping_sub(recovery) as i16 >= 0)
+        {
+            self.recovery = None;
+        }
+
+        if self.cwnd < self.ssthresh {
+            self.cwnd = (self.cwnd + frames).min(self.ssthresh);
+            return;
+        }
+
+        self.acked += frames;
+        if self.acked >= self.cwnd {
+            self.acked -= self.cwnd;
+            self.cwnd = (self.cwnd + 1).min(self.max_window);
+        }
+    }
+
+    fn on_loss(&mut self, next_id: u16) {
+        if self.recovery.is_some() {
+            return;
+        }
+
+        self.decrease(next_id);
+        self.cwnd = self.ssthresh;
+        println!("CONGESTION window {} frames", self.cwnd);
+    }
+
+    fn on_timeout(&mut self, next_id: u16) {
+        self.decrease(next_id);
+        self.cwnd = 1;
+        println!("CONGESTION window {} frames", self.cwnd);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Acknowledges the whole window one frame at a time, as a round trip would.
+    fn round_trip(aimd: &mut Aimd, base: &mut u16) {
+        for _ in 0..aimd.window() {
+            *base = base.wrapping_add(1);
+            aimd.on_ack(1, *base);
+        }
+    }
+
+    #[test]
+    fn slow_start_doubles_the_window_every_round_trip() {
+        let mut aimd = Aimd::new(64);
+        let mut base = 0;
+
+        let windows: Vec<usize> = (0..5)
+            .map(|_| {
+                round_trip(&mut aimd, &mut base);
+                aimd.window()
+            })
+            .collect();
+
+        assert_eq!(windows, [2, 4, 8, 16, 32]);
+    }
+
+    #[test]
+    fn congestion_avoidance_adds_one_frame_per_round_trip() {
+        let mut aimd = Aimd::new(64);
+        let mut base = 0;
+        for _ in 0..4 {
+            round_trip(&mut aimd, &mut base);
+        }
+        aimd.on_loss(base.wrapping_add(16));
+        assert_eq!(aimd.window(), 8);
+        base = base.wrapping_add(16);
+
+        let windows: Vec<usize> = (0..3)
+            .map(|_| {
+                round_trip(&mut aimd, &mut base);
+                aimd.window()
+            })
+            .collect();
+
+        assert_eq!(windows, [9, 10, 11]);
+    }
+
+    #[test]
+    fn window_never_exceeds_the_maximum() {
+        let mut aimd = Aimd::new(6);
+        let mut base = 0;
+        for _ in 0..10 {
+            round_trip(&mut aimd, &mut base);
+        }
+
+        assert_eq!(aimd.window(), 6);
+    }
+
+    #[test]
+    fn losses_of_one_window_shrink_it_once() {
+        let mut aimd = Aimd::new(64);
+        let mut base = 0;
+        for _ in 0..4 {
+            round_trip(&mut aimd, &mut base);
+        }
+        let next_id = base.wrapping_add(16);
+
+        aimd.on_loss(next_id);
+        aimd.on_loss(next_id);
+        aimd.on_loss(next_id);
+        assert_eq!(aimd.window(), 8);
+
+        // Once the frames sent before the reduction are acknowledged, a new loss counts
+        aimd.on_ack(1, next_id);
+        aimd.on_loss(next_id.wrapping_add(8));
+        assert_eq!(aimd.window(), 4);
+    }
+
+    #[test]
+    fn timeout_restarts_slow_start_from_one_frame() {
+        let mut aimd = Aimd::new(64);
+        let mut base = 0;
+        for _ in 0..4 {
+            round_trip(&mut aimd, &mut base);
+        }
+
+        aimd.on_timeout(base.wrapping_add(16));
+        assert_eq!(aimd.window(), 1);
+
+        // A NAK of a frame sent before the timeout does not shrink it any further
+        aimd.on_loss(base.wrapping_add(16));
+        assert_eq!(aimd.window(), 1);
+
+        base = base.wrapping_add(16);
+        let windows: Vec<usize> = (0..4)
+            .map(|_| {
+                round_trip(&mut aimd, &mut base);
+                aimd.window()
+            })
+            .collect();
+        assert_eq!(windows, [2, 4, 8, 9]);
+    }
+
+    #[test]
+    fn recovery_point_survives_id_wraparound() {
+        let mut aimd = Aimd::new(64);
+        let mut base = u16::MAX - 20;
+        for _ in 0..4 {
+            round_trip(&mut aimd, &mut base);
+        }
+        let next_id = base.wrapping_add(16);
+
+        aimd.on_loss(next_id);
+        aimd.on_ack(1, base.wrapping_add(1));
+        aimd.on_loss(next_id);
+        assert_eq!(aimd.window(), 8);
+
+        aimd.on_ack(1, next_id);
+        aimd.on_loss(next_id.wrapping_add(1));
+        assert_eq!(aimd.window(), 4);
+    }
+}
@@ -0,0 +1,169 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+
+use super::network::{self, Flags, FrameFormat, Payload, PayloadError, PayloadErrorKind};
+
+const SYNC_PATTERN: [u8; 8] = [0xDC, 0xC0, 0x23, 0xC2, 0xDC, 0xC0, 0x23, 0xC2];
+
+pub enum DecodeStep {
+    /// The buffer does not yet hold a complete frame.
+    NeedMore,
+    /// Bytes that can never start a frame and must be dropped.
+    Discard(usize),
+    /// A valid frame, the bytes it occupied and the data bytes repaired by FEC.
+    Frame {
+        payload: Payload,
+        len: usize,
+        corrected: usize,
+    },
+    /// A frame candidate that failed validation, the bytes it spanned as far as
+    /// they were read and the bytes to drop to resync.
+    Invalid {
+        error: PayloadError,
+        len: usize,
+        skip: usize,
+    },
+}
+
+fn find_sync(buf: &[u8]) -> Option<usize> {
+    buf.windows(SYNC_PATTERN.len())
+        .position(|window| window == SYNC_PATTERN)
+}
+
+/// Id and copy bit of the frame starting `buf` if its header still looks like
+/// that of a data frame, possibly carrying an ACK, so a failed checksum most
+/// likely hit its data.
+fn data_frame_id(buf: &[u8]) -> Option<(u16, Flags)> {
+    let length = u16::from_be_bytes([buf[10], buf[11]]);
+    let flag = Flags::from_bits(buf[14]);
+
+    (length > 0 && flag.difference(Flags::PIGGYBACK | Flags::COPY).is_empty())
+        .then(|| (u16::from_be_bytes([buf[12], buf[13]]), flag & Flags::COPY))
+}
+
+pub fn decode_step(buf: &[u8], format: &FrameFormat) -> DecodeStep {
+    let start = match find_sync(buf) {
+        Some(start) => start,
+        None => {
+            // Keep the tail in case it holds the beginning of a SYNC split across reads
+            let keep = SYNC_PATTERN.len() - 1;
+            if buf.len() > keep {
+                return DecodeStep::Discard(buf.len() - keep);
+            }
+            return DecodeStep::NeedMore;
+        }
+    };
+
+    if start > 0 {
+        return DecodeStep::Discard(start);
+    }
+
+    if buf.len() < network::PAYLOAD_HEADER_SIZE {
+        return DecodeStep::NeedMore;
+    }
+
+    let length = u16::from_be_bytes([buf[10], buf[11]]) as usize;
+    if length > format.mtu {
+        return DecodeStep::Invalid {
+            error: PayloadError::new(
+                PayloadErrorKind::InvalidData,
+                &format!("Length {} exceeds MTU of {}", length, format.mtu),
+            ),
+            len: network::PAYLOAD_HEADER_SIZE,
+            skip: 1,
+        };
+    }
+
+    let frame_size = format.frame_size(length);
+    if buf.len() < frame_size {
+        return DecodeStep::NeedMore;
+    }
+
+    match Payload::from_bytes_corrected(&buf[..frame_size], format) {
+        Ok((payload, corrected)) => DecodeStep::Frame {
+            payload,
+            len: frame_size,
+            corrected,
+        },
+        // Slide past this SYNC so the next candidate can be tried
+        Err(error) => DecodeStep::Invalid {
+            error,
+            len: frame_size,
+            skip: 1,
+        },
+    }
+}
+
+/// Accumulates bytes from a stream and splits them into frames, resynchronizing
+/// on the SYNC pattern whenever a candidate frame turns out to be corrupted.
+pub struct FrameDecoder {
+    buf: Vec<u8>,
+    format: FrameFormat,
+    /// Data bytes repaired by FEC so far.
+    corrected: Arc<AtomicUsize>,
+    /// Id of the last data frame that failed its checksum, until taken.
+    corrupted: Option<(u16, Flags)>,
+}
+
+impl FrameDecoder {
+    pub fn new(format: FrameFormat) -> Self {
+        Self {
+            buf: Vec::new(),
+            format,
+            corrected: Arc::default(),
+            corrupted: None,
+        }
+    }
+
+    pub fn format(&self) -> &FrameFormat {
+        &self.format
+    }
+
+    /// Data bytes repaired by FEC so far, shared so they can be read while the
+    /// decoder is in use elsewhere.
+    pub fn corrected(&self) -> &Arc<AtomicUsize> {
+        &self.corrected
+    }
+
+    /// Takes the id and copy bit of the last data frame rejected for a failed
+    /// checksum.
+    pub fn take_corrupted(&mut self) -> Option<(u16, Flags)> {
+        self.corrupted.take()
+    }
+
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Next frame, or the reason the next candidate was rejected, together with
+    /// the bytes it spanned as read from the stream.
+    pub fn next_payload(&mut self) -> Option<(Result<Payload, PayloadError>, Vec<u8>)> {
+        loop {
+            match decode_step(&self.buf, &self.format) {
+                DecodeStep::NeedMore => return None,
+                DecodeStep::Discard(n) => {
+                    self.buf.drain(..n);
+                }
+                DecodeStep::Frame {
+                    payload,
+                    len,
+                    corrected,
+                } => {
+                    let raw = self.buf.drain(..len).collect();
+                    self.corrected.fetch_add(corrected, Ordering::Relaxed);
+                    return Some((Ok(payload), raw));
+                }
+                DecodeStep::Invalid { error, len, skip } => {
+                    if error.kind == PayloadErrorKind::ChecksumMismatch {
+                        self.corrupted = data_frame_id(&self.buf);
+                    }
+                    let raw = self.buf[..len].to_vec();
+                    self.buf.drain(..skip);
+                    return Some((Err(error), raw));
+                }
+            }
+        }
+    }
+}
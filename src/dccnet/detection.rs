@@ -0,0 +1,194 @@
+use std::{fmt, str::FromStr};
+
+/// An error-detection code computed over a frame whose checksum field is zeroed.
+///
+/// The first two bytes of the code (big-endian) go in the header checksum field;
+/// codes wider than 16 bits append the remaining bytes right after the data.
+pub trait ErrorDetector: Sync {
+    fn name(&self) -> &'static str;
+
+    /// Width of the code in bytes.
+    fn code_len(&self) -> usize;
+
+    /// Code for `frame`, right-aligned in a `u32`.
+    fn compute(&self, frame: &[u8]) -> u32;
+
+    fn code_bytes(&self, frame: &[u8]) -> Vec<u8> {
+        let code = self.compute(frame).to_be_bytes();
+        code[code.len() - self.code_len()..].to_vec()
+    }
+}
+
+/// RFC 1071 Internet checksum, as required by the DCCNET specification.
+pub struct InternetChecksum;
+
+impl ErrorDetector for InternetChecksum {
+    fn name(&self) -> &'static str {
+        "internet"
+    }
+
+    fn code_len(&self) -> usize {
+        2
+    }
+
+    fn compute(&self, frame: &[u8]) -> u32 {
+        let mut sum: u32 = 0;
+
+        // Iterate over the bytes two at a time (16 bits)
+        let mut i = 0;
+        while i < frame.len() {
+            let word = if i + 1 < frame.len() {
+                u16::from_be_bytes([frame[i], frame[i + 1]])
+            } else {
+                u16::from_be_bytes([frame[i], 0]) // Padding if odd number of bytes
+            };
+            sum += word as u32;
+
+            // Carry-around addition
+            if sum > 0xFFFF {
+                sum = (sum & 0xFFFF) + (sum >> 16);
+            }
+            i += 2;
+        }
+
+        // One's complement of the result
+        !(sum as u16) as u32
+    }
+}
+
+const CRC16_TABLE: [u16; 256] = crc16_table(0x1021);
+const CRC32_TABLE: [u32; 256] = crc32_table(0xEDB88320);
+
+const fn crc16_table(poly: u16) -> [u16; 256] {
+    let mut table = [0u16; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u16) << 8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ poly } else { crc << 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+const fn crc32_table(poly: u32) -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ poly } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// CRC-16-CCITT (polynomial 0x1021, initial value 0xFFFF, no reflection).
+pub struct Crc16Ccitt;
+
+impl ErrorDetector for Crc16Ccitt {
+    fn name(&self) -> &'static str {
+        "crc16"
+    }
+
+    fn code_len(&self) -> usize {
+        2
+    }
+
+    fn compute(&self, frame: &[u8]) -> u32 {
+        let crc = frame.iter().fold(0xFFFFu16, |crc, &byte| {
+            (crc << 8) ^ CRC16_TABLE[((crc >> 8) as u8 ^ byte) as usize]
+        });
+        crc as u32
+    }
+}
+
+/// CRC-32 as used by Ethernet (reflected polynomial 0xEDB88320).
+pub struct Crc32;
+
+impl ErrorDetector for Crc32 {
+    fn name(&self) -> &'static str {
+        "crc32"
+    }
+
+    fn code_len(&self) -> usize {
+        4
+    }
+
+    fn compute(&self, frame: &[u8]) -> u32 {
+        let crc = frame.iter().fold(0xFFFFFFFFu32, |crc, &byte| {
+            (crc >> 8) ^ CRC32_TABLE[((crc as u8) ^ byte) as usize]
+        });
+        !crc
+    }
+}
+
+/// Selects the error-detection code used on a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Detection {
+    #[default]
+    Internet,
+    Crc16,
+    Crc32,
+}
+
+impl Detection {
+    pub fn detector(&self) -> &'static dyn ErrorDetector {
+        match self {
+            Detection::Internet => &InternetChecksum,
+            Detection::Crc16 => &Crc16Ccitt,
+            Detection::Crc32 => &Crc32,
+        }
+    }
+
+    /// Bytes of the code carried after the data, beyond the header checksum field.
+    pub fn trailer_len(&self) -> usize {
+        self.detector().code_len().saturating_sub(2)
+    }
+}
+
+impl FromStr for Detection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "internet" => Ok(Detection::Internet),
+            "crc16" => Ok(Detection::Crc16),
+            "crc32" => Ok(Detection::Crc32),
+            _ => Err(format!("Unknown error detection algorithm: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Detection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.detector().name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHECK_INPUT: &[u8] = b"123456789";
+
+    #[test]
+    fn crc16_matches_check_value() {
+        assert_eq!(Crc16Ccitt.compute(CHECK_INPUT), 0x29B1);
+        assert_eq!(Crc16Ccitt.code_bytes(CHECK_INPUT), [0x29, 0xB1]);
+    }
+
+    #[test]
+    fn crc32_matches_check_value() {
+        assert_eq!(Crc32.compute(CHECK_INPUT), 0xCBF43926);
+        assert_eq!(Crc32.code_bytes(CHECK_INPUT), [0xCB, 0xF4, 0x39, 0x26]);
+    }
+}
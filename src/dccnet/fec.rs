@@ -0,0 +1,362 @@
+use std::{fmt, str::FromStr};
+
+const GF_PRIMITIVE: u16 = 0x11D;
+const BLOCK_SIZE: usize = 255;
+
+const fn gf_tables() -> ([u8; 512], [u8; 256]) {
+    let mut exp = [0u8; 512];
+    let mut log = [0u8; 256];
+    let mut x: u16 = 1;
+    let mut i = 0;
+    while i < 255 {
+        exp[i] = x as u8;
+        log[x as usize] = i as u8;
+        x <<= 1;
+        if x & 0x100 != 0 {
+            x ^= GF_PRIMITIVE;
+        }
+        i += 1;
+    }
+    while i < 512 {
+        exp[i] = exp[i - 255];
+        i += 1;
+    }
+    (exp, log)
+}
+
+const GF_TABLES: ([u8; 512], [u8; 256]) = gf_tables();
+const GF_EXP: [u8; 512] = GF_TABLES.0;
+const GF_LOG: [u8; 256] = GF_TABLES.1;
+
+fn gf_mul(x: u8, y: u8) -> u8 {
+    if x == 0 || y == 0 {
+        return 0;
+    }
+    GF_EXP[GF_LOG[x as usize] as usize + GF_LOG[y as usize] as usize]
+}
+
+fn gf_div(x: u8, y: u8) -> u8 {
+    if x == 0 {
+        return 0;
+    }
+    GF_EXP[(GF_LOG[x as usize] as usize + 255 - GF_LOG[y as usize] as usize) % 255]
+}
+
+fn gf_pow(x: u8, power: i32) -> u8 {
+    GF_EXP[(GF_LOG[x as usize] as i32 * power).rem_euclid(255) as usize]
+}
+
+fn gf_inverse(x: u8) -> u8 {
+    GF_EXP[255 - GF_LOG[x as usize] as usize]
+}
+
+// Polynomials are stored with the highest degree coefficient first
+
+fn poly_scale(p: &[u8], x: u8) -> Vec<u8> {
+    p.iter().map(|&c| gf_mul(c, x)).collect()
+}
+
+fn poly_add(p: &[u8], q: &[u8]) -> Vec<u8> {
+    let len = p.len().max(q.len());
+    let mut r = vec![0u8; len];
+    for (i, &c) in p.iter().enumerate() {
+        r[i + len - p.len()] = c;
+    }
+    for (i, &c) in q.iter().enumerate() {
+        r[i + len - q.len()] ^= c;
+    }
+    r
+}
+
+fn poly_mul(p: &[u8], q: &[u8]) -> Vec<u8> {
+    let mut r = vec![0u8; p.len() + q.len() - 1];
+    for (j, &qc) in q.iter().enumerate() {
+        for (i, &pc) in p.iter().enumerate() {
+            r[i + j] ^= gf_mul(pc, qc);
+        }
+    }
+    r
+}
+
+fn poly_eval(p: &[u8], x: u8) -> u8 {
+    p.iter().skip(1).fold(p[0], |y, &c| gf_mul(y, x) ^ c)
+}
+
+/// Remainder of dividing by a monic `divisor`.
+fn poly_rem(dividend: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut out = dividend.to_vec();
+    for i in 0..dividend.len().saturating_sub(divisor.len() - 1) {
+        let coef = out[i];
+        if coef != 0 {
+            for j in 1..divisor.len() {
+                out[i + j] ^= gf_mul(divisor[j], coef);
+            }
+        }
+    }
+    out.split_off(out.len() - (divisor.len() - 1))
+}
+
+fn generator_poly(nsym: usize) -> Vec<u8> {
+    (0..nsym).fold(vec![1], |g, i| poly_mul(&g, &[1, gf_pow(2, i as i32)]))
+}
+
+fn syndromes(codeword: &[u8], nsym: usize) -> Vec<u8> {
+    // The leading zero keeps the indices aligned with the textbook formulas
+    std::iter::once(0)
+        .chain((0..nsym).map(|i| poly_eval(codeword, gf_pow(2, i as i32))))
+        .collect()
+}
+
+fn error_locator(synd: &[u8], nsym: usize) -> Option<Vec<u8>> {
+    let shift = synd.len() - nsym;
+    let mut err_loc = vec![1u8];
+    let mut old_loc = vec![1u8];
+
+    // Berlekamp-Massey
+    for i in 0..nsym {
+        let k = i + shift;
+        let mut delta = synd[k];
+        for j in 1..err_loc.len() {
+            delta ^= gf_mul(err_loc[err_loc.len() - 1 - j], synd[k - j]);
+        }
+
+        old_loc.push(0);
+        if delta != 0 {
+            if old_loc.len() > err_loc.len() {
+                let new_loc = poly_scale(&old_loc, delta);
+                old_loc = poly_scale(&err_loc, gf_inverse(delta));
+                err_loc = new_loc;
+            }
+            err_loc = poly_add(&err_loc, &poly_scale(&old_loc, delta));
+        }
+    }
+
+    let leading = err_loc.iter().take_while(|&&c| c == 0).count();
+    err_loc.drain(..leading);
+
+    if (err_loc.len() - 1) * 2 > nsym {
+        return None;
+    }
+
+    Some(err_loc)
+}
+
+fn error_positions(err_loc: &[u8], len: usize) -> Option<Vec<usize>> {
+    // Chien search over the reversed locator
+    let reversed: Vec<u8> = err_loc.iter().rev().copied().collect();
+    let positions: Vec<usize> = (0..len)
+        .filter(|&i| poly_eval(&reversed, gf_pow(2, i as i32)) == 0)
+        .map(|i| len - 1 - i)
+        .collect();
+
+    if positions.len() != err_loc.len() - 1 {
+        return None;
+    }
+
+    Some(positions)
+}
+
+fn correct_errata(codeword: &mut [u8], synd: &[u8], err_pos: &[usize]) {
+    // Forney algorithm
+    let coef_pos: Vec<usize> = err_pos.iter().map(|&p| codeword.len() - 1 - p).collect();
+    let errata_loc = coef_pos.iter().fold(vec![1u8], |loc, &i| {
+        poly_mul(&loc, &poly_add(&[1], &[gf_pow(2, i as i32), 0]))
+    });
+
+    let nsym = errata_loc.len() - 1;
+    let synd_rev: Vec<u8> = synd.iter().rev().copied().collect();
+    let mut modulus = vec![0u8; nsym + 2];
+    modulus[0] = 1;
+    let err_eval = poly_rem(&poly_mul(&synd_rev, &errata_loc), &modulus);
+
+    let x: Vec<u8> = coef_pos
+        .iter()
+        .map(|&p| gf_pow(2, -(255 - p as i32)))
+        .collect();
+
+    for (i, &xi) in x.iter().enumerate() {
+        let xi_inv = gf_inverse(xi);
+        let loc_prime = x
+            .iter()
+            .enumerate()
+            .filter(|&(j, _)| j != i)
+            .fold(1u8, |acc, (_, &xj)| gf_mul(acc, 1 ^ gf_mul(xi_inv, xj)));
+
+        let y = gf_mul(xi, poly_eval(&err_eval, xi_inv));
+        codeword[err_pos[i]] ^= gf_div(y, loc_prime);
+    }
+}
+
+/// Reed-Solomon code over GF(256) applied to the data of each frame.
+///
+/// Data is split into blocks of `255 - parity` bytes, each followed by `parity`
+/// check bytes, so up to `parity / 2` corrupted bytes per block can be corrected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fec {
+    parity: usize,
+}
+
+impl Fec {
+    pub fn new(parity: usize) -> Result<Self, String> {
+        if !(2..BLOCK_SIZE).contains(&parity) {
+            return Err(format!(
+                "FEC parity must be between 2 and {} bytes, got {}",
+                BLOCK_SIZE - 1,
+                parity
+            ));
+        }
+
+        Ok(Self { parity })
+    }
+
+    pub fn parity(&self) -> usize {
+        self.parity
+    }
+
+    /// Corrupted bytes that can be corrected in each block.
+    pub fn max_correctable(&self) -> usize {
+        self.parity / 2
+    }
+
+    fn block_data_size(&self) -> usize {
+        BLOCK_SIZE - self.parity
+    }
+
+    pub fn encoded_len(&self, len: usize) -> usize {
+        len + len.div_ceil(self.block_data_size()) * self.parity
+    }
+
+    pub fn encode(&self, data: &[u8]) -> Vec<u8> {
+        let generator = generator_poly(self.parity);
+        let mut encoded = Vec::with_capacity(self.encoded_len(data.len()));
+
+        for block in data.chunks(self.block_data_size()) {
+            let mut dividend = block.to_vec();
+            dividend.resize(block.len() + self.parity, 0);
+
+            encoded.extend_from_slice(block);
+            encoded.extend_from_slice(&poly_rem(&dividend, &generator));
+        }
+
+        encoded
+    }
+
+    /// Returns the data with every correctable block repaired and the number of
+    /// bytes corrected. Blocks beyond repair are passed through unchanged.
+    pub fn decode(&self, encoded: &[u8]) -> (Vec<u8>, usize) {
+        let mut data = Vec::with_capacity(encoded.len());
+        let mut corrected = 0;
+
+        for block in encoded.chunks(BLOCK_SIZE) {
+            let data_len = block.len().saturating_sub(self.parity);
+            match self.correct_block(block) {
+                Some((codeword, errors)) => {
+                    data.extend_from_slice(&codeword[..data_len]);
+                    corrected += errors;
+                }
+                None => data.extend_from_slice(&block[..data_len]),
+            }
+        }
+
+        (data, corrected)
+    }
+
+    fn correct_block(&self, block: &[u8]) -> Option<(Vec<u8>, usize)> {
+        if block.len() <= self.parity {
+            return None;
+        }
+
+        let mut codeword = block.to_vec();
+        let synd = syndromes(&codeword, self.parity);
+        if synd.iter().all(|&s| s == 0) {
+            return Some((codeword, 0));
+        }
+
+        let err_loc = error_locator(&synd, self.parity)?;
+        let err_pos = error_positions(&err_loc, codeword.len())?;
+        correct_errata(&mut codeword, &synd, &err_pos);
+
+        if syndromes(&codeword, self.parity).iter().any(|&s| s != 0) {
+            return None;
+        }
+
+        Some((codeword, err_pos.len()))
+    }
+}
+
+impl FromStr for Fec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parity = s
+            .parse::<usize>()
+            .map_err(|_| format!("Invalid FEC parity: {}", s))?;
+        Fec::new(parity)
+    }
+}
+
+impl fmt::Display for Fec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RS({}, {})", BLOCK_SIZE, self.block_data_size())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PARITY: usize = 8;
+
+    /// Three blocks, the last one partial.
+    fn data() -> Vec<u8> {
+        (0..600u32).map(|i| (i * 31 + 7) as u8).collect()
+    }
+
+    /// Flips `count` bytes of every block, spread over its data and parity bytes.
+    fn corrupt(encoded: &mut [u8], count: usize) {
+        for block in encoded.chunks_mut(BLOCK_SIZE) {
+            let step = block.len() / count;
+            for i in 0..count {
+                block[i * step] ^= 0x5A;
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips_without_errors() {
+        let fec = Fec::new(PARITY).unwrap();
+        let encoded = fec.encode(&data());
+
+        assert_eq!(encoded.len(), fec.encoded_len(data().len()));
+        assert_eq!(fec.decode(&encoded), (data(), 0));
+    }
+
+    #[test]
+    fn corrects_up_to_half_the_parity_in_every_block() {
+        let fec = Fec::new(PARITY).unwrap();
+        let mut encoded = fec.encode(&data());
+        corrupt(&mut encoded, fec.max_correctable());
+
+        assert_eq!(fec.decode(&encoded), (data(), 3 * fec.max_correctable()));
+    }
+
+    #[test]
+    fn fails_past_half_the_parity() {
+        let fec = Fec::new(PARITY).unwrap();
+        let mut encoded = fec.encode(&data());
+        corrupt(&mut encoded[..BLOCK_SIZE], fec.max_correctable() + 1);
+
+        let (decoded, _) = fec.decode(&encoded);
+        let first = fec.block_data_size();
+        assert_ne!(decoded[..first], data()[..first]);
+        // The other blocks are unaffected
+        assert_eq!(decoded[first..], data()[first..]);
+    }
+
+    #[test]
+    fn rejects_parity_out_of_range() {
+        assert!(Fec::new(1).is_err());
+        assert!(Fec::new(BLOCK_SIZE).is_err());
+        assert!(Fec::new(2).is_ok());
+    }
+}
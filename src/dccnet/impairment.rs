@@ -0,0 +1,190 @@
+use std::sync::{
+    Mutex,
+    atomic::{AtomicUsize, Ordering},
+};
+
+/// Bit error rates emulating a noisy link, applied to the bytes this end writes
+/// and reads. Both are disabled by default.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Impairment {
+    /// Probability of each outgoing bit being flipped.
+    pub outgoing: f64,
+    /// Probability of each incoming bit being flipped.
+    pub incoming: f64,
+    /// Picks which bits are flipped, so a run with the same seed and the same
+    /// bytes flips the same bits.
+    pub seed: u64,
+}
+
+/// Distinguishes the incoming stream so it does not flip the same bit positions
+/// as the outgoing one.
+const INCOMING_STREAM: u64 = 0x5DEE_CE66_D1CE_4E5B;
+
+impl Impairment {
+    pub fn outgoing(&self) -> Option<BitErrors> {
+        (self.outgoing > 0.0).then(|| BitErrors::new(self.outgoing, self.seed))
+    }
+
+    pub fn incoming(&self) -> Option<BitErrors> {
+        (self.incoming > 0.0).then(|| BitErrors::new(self.incoming, self.seed ^ INCOMING_STREAM))
+    }
+}
+
+/// Parses a bit error rate, a probability greater than 0 and at most 1.
+pub fn parse_rate(name: &str, value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(rate) if rate > 0.0 && rate <= 1.0 => Ok(rate),
+        _ => Err(format!("Invalid value for {}: {} (rate from 0 to 1 expected)", name, value)),
+    }
+}
+
+struct Generator {
+    /// SplitMix64 state, which is all the randomness this needs.
+    state: u64,
+    /// Bits to let through before flipping the next one.
+    gap: u64,
+}
+
+impl Generator {
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Draws the distance to the next error, which is geometrically distributed
+    /// since every bit is flipped independently.
+    fn next_gap(&mut self, rate: f64) -> u64 {
+        if rate >= 1.0 {
+            return 0;
+        }
+
+        // Uniform in (0, 1], so the logarithm is finite
+        let uniform = ((self.next_u64() >> 11) as f64 + 1.0) / (1u64 << 53) as f64;
+        (uniform.ln() / (-rate).ln_1p()) as u64
+    }
+}
+
+/// Flips bits of a byte stream independently at a given rate, deterministically
+/// for a given seed.
+pub struct BitErrors {
+    rate: f64,
+    generator: Mutex<Generator>,
+    flipped: AtomicUsize,
+}
+
+impl BitErrors {
+    pub fn new(rate: f64, seed: u64) -> Self {
+        let mut generator = Generator {
+            state: seed,
+            gap: 0,
+        };
+        generator.gap = generator.next_gap(rate);
+
+        Self {
+            rate,
+            generator: Mutex::new(generator),
+            flipped: AtomicUsize::new(0),
+        }
+    }
+
+    /// Corrupts the next bytes of the stream in place.
+    pub fn apply(&self, bytes: &mut [u8]) {
+        let mut generator = self.generator.lock().unwrap_or_else(|e| e.into_inner());
+        let bits = bytes.len() as u64 * 8;
+        let mut position = 0u64;
+
+        loop {
+            let next = position.saturating_add(generator.gap);
+            if next >= bits {
+                generator.gap = next - bits;
+                return;
+            }
+
+            bytes[(next / 8) as usize] ^= 0x80 >> (next % 8);
+            self.flipped.fetch_add(1, Ordering::Relaxed);
+            position = next + 1;
+            generator.gap = generator.next_gap(self.rate);
+        }
+    }
+
+    /// Bits flipped so far.
+    pub fn flipped(&self) -> usize {
+        self.flipped.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEN: usize = 1 << 20;
+
+    /// Bits that differ between the buffer and its original, all zeros.
+    fn differing_bits(bytes: &[u8]) -> usize {
+        bytes.iter().map(|byte| byte.count_ones() as usize).sum()
+    }
+
+    #[test]
+    fn same_seed_flips_same_bits() {
+        let mut first = vec![0u8; 4096];
+        let mut second = vec![0u8; 4096];
+        BitErrors::new(0.01, 42).apply(&mut first);
+        BitErrors::new(0.01, 42).apply(&mut second);
+        assert_eq!(first, second);
+
+        let mut other = vec![0u8; 4096];
+        BitErrors::new(0.01, 43).apply(&mut other);
+        assert_ne!(first, other);
+    }
+
+    #[test]
+    fn stream_split_across_calls_flips_same_bits() {
+        let mut whole = vec![0u8; 4096];
+        BitErrors::new(0.01, 7).apply(&mut whole);
+
+        let mut split = vec![0u8; 4096];
+        let errors = BitErrors::new(0.01, 7);
+        for chunk in split.chunks_mut(100) {
+            errors.apply(chunk);
+        }
+        assert_eq!(whole, split);
+    }
+
+    #[test]
+    fn flip_rate_is_close_to_configured_rate() {
+        let rate = 0.001;
+        let mut bytes = vec![0u8; LEN];
+        BitErrors::new(rate, 1).apply(&mut bytes);
+
+        // About 8389 flips expected, with a standard deviation below 92
+        let expected = LEN as f64 * 8.0 * rate;
+        let flipped = differing_bits(&bytes) as f64;
+        assert!(
+            (flipped - expected).abs() < expected * 0.05,
+            "{flipped} bits flipped, {expected} expected"
+        );
+    }
+
+    #[test]
+    fn flipped_count_matches_difference() {
+        let errors = BitErrors::new(0.003, 9);
+        let mut bytes = vec![0u8; LEN];
+        for chunk in bytes.chunks_mut(1500) {
+            errors.apply(chunk);
+        }
+        assert!(errors.flipped() > 0);
+        assert_eq!(errors.flipped(), differing_bits(&bytes));
+    }
+
+    #[test]
+    fn rate_of_one_flips_every_bit() {
+        let errors = BitErrors::new(1.0, 0);
+        let mut bytes = vec![0u8; 16];
+        errors.apply(&mut bytes);
+        assert_eq!(bytes, [0xFF; 16]);
+        assert_eq!(errors.flipped(), 128);
+    }
+}
@@ -0,0 +1,48 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::{
+    task::JoinHandle,
+    time::{Instant, sleep_until},
+};
+
+use super::{
+    communication::FrameWriter,
+    network::{self, Flags, Payload},
+    options::FrameOption,
+    state::StateMachine,
+};
+
+/// Whether the frame only shows the peer is alive and carries nothing else.
+pub fn is_keepalive(payload: &Payload) -> bool {
+    payload.flag == Flags::OPT && FrameOption::decode_all(&payload.data) == [FrameOption::Keepalive]
+}
+
+/// Writes a keepalive frame whenever nothing was written for `interval`, until
+/// the connection is over or the stream fails.
+pub fn keepalive_loop(
+    writer: Arc<FrameWriter>,
+    state: Arc<StateMachine>,
+    interval: Duration,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let data = FrameOption::encode_all(&[FrameOption::Keepalive]);
+        let payload = writer.payload(data, network::START_ID, Flags::OPT);
+
+        loop {
+            sleep_until(writer.last_write() + interval).await;
+
+            if state.state().is_final() {
+                break;
+            }
+            if writer.last_write() + interval > Instant::now() {
+                continue;
+            }
+
+            println!("SEND KEEPALIVE");
+            if let Err(e) = writer.write_payload(&payload).await {
+                eprintln!("Failed to send keepalive: {}", e);
+                break;
+            }
+        }
+    })
+}
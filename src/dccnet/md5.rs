@@ -1,32 +1,29 @@
-use std::sync::Arc;
-
-use tokio::{
-    net::{TcpStream, tcp::OwnedWriteHalf},
-    sync::Mutex,
-};
+use tokio::net::TcpStream;
 
 use super::{
     communication::{self, NetworkError, NetworkErrorKind},
-    network::{self, Payload},
-    sync_read,
+    config::DccnetConfig,
+    network::{self, Flags},
+    session::Session,
 };
 
 async fn validate_gas(
-    stream_white: &Mutex<OwnedWriteHalf>,
+    session: &Session,
     mut gas: Vec<u8>,
 ) -> Result<u16, NetworkError> {
     gas.push(b'\n');
 
-    let gas_payload = Payload::new(gas, network::START_ID, network::FLAG_SED);
+    let gas_payload = session.writer().payload(gas, network::START_ID, Flags::NONE);
 
-    communication::send_frame(stream_white, &gas_payload).await?;
+    communication::send_frame(session, &gas_payload).await?;
 
     let mut id = communication::next_id(gas_payload.id);
 
     let payload = loop {
-        match communication::receive_frame(stream_white).await {
+        match communication::receive_frame(session).await {
             Ok(payload) => break payload,
-            Err(_) => continue,
+            Err(e) if e.kind == NetworkErrorKind::UnexpectedFlagError => continue,
+            Err(e) => return Err(e),
         }
     };
 
@@ -41,8 +38,8 @@ async fn validate_gas(
     let md5_hash = md5::compute(payload_data);
     let rash_string = format!("{:x}\n", md5_hash);
 
-    let send_payload = Payload::new(rash_string.as_bytes().to_vec(), id, network::FLAG_SED);
-    communication::send_frame(stream_white, &send_payload).await?;
+    let send_payload = session.writer().payload(rash_string.as_bytes().to_vec(), id, Flags::NONE);
+    communication::send_frame(session, &send_payload).await?;
 
     id = communication::next_id(payload.id);
 
@@ -60,27 +57,29 @@ fn trim_data_payload(data: Vec<u8>) -> Result<Vec<u8>, NetworkError> {
     }
 }
 
-async fn read_date_from_server(stream: TcpStream, gas: Vec<u8>) -> Result<(), NetworkError> {
+async fn read_date_from_server(
+    stream: TcpStream,
+    gas: Vec<u8>,
+    config: &DccnetConfig,
+) -> Result<(), NetworkError> {
     let mut payload_data = vec![];
+    let mut session = Session::new(stream, config.clone());
 
-    let (read_half, write_half) = stream.into_split();
-    let stream_read = Arc::new(Mutex::new(read_half));
-    let stream_white = Arc::new(Mutex::new(write_half));
-
-    sync_read::read_stream_data_loop(stream_read).await;
-    let mut id = validate_gas(&stream_white, gas).await?;
+    session.start();
+    let mut id = validate_gas(&session, gas).await?;
 
     loop {
-        let payload = match communication::receive_frame(&stream_white).await {
+        let payload = match communication::receive_frame(&session).await {
             Ok(payload) => payload,
-            Err(_) => continue,
+            Err(e) if e.kind == NetworkErrorKind::UnexpectedFlagError => continue,
+            Err(e) => return Err(e),
         };
 
-        if payload.flag == network::FLAG_END {
+        if payload.flag.contains(Flags::END) {
             break;
         }
 
-        if payload.flag == network::FLAG_ACK {
+        if payload.flag.contains(Flags::ACK) {
             continue;
         }
 
@@ -98,9 +97,9 @@ async fn read_date_from_server(stream: TcpStream, gas: Vec<u8>) -> Result<(), Ne
             let md5_hash = md5::compute(data);
             let rash_string = format!("{:x}\n", md5_hash);
 
-            let send_payload = Payload::new(rash_string.as_bytes().to_vec(), id, network::FLAG_SED);
+            let send_payload = session.writer().payload(rash_string.as_bytes().to_vec(), id, Flags::NONE);
 
-            communication::send_frame(&stream_white, &send_payload).await?;
+            communication::send_frame(&session, &send_payload).await?;
 
             id = communication::next_id(id);
         }
@@ -112,7 +111,11 @@ async fn read_date_from_server(stream: TcpStream, gas: Vec<u8>) -> Result<(), Ne
     Ok(())
 }
 
-pub async fn handle_tcp_communication(stream: TcpStream, gas: Vec<u8>) -> Result<(), NetworkError> {
-    read_date_from_server(stream, gas).await?;
+pub async fn handle_tcp_communication(
+    stream: TcpStream,
+    gas: Vec<u8>,
+    config: &DccnetConfig,
+) -> Result<(), NetworkError> {
+    read_date_from_server(stream, gas, config).await?;
     Ok(())
 }
@@ -1,7 +1,23 @@
+pub mod ack;
+pub mod arq;
+pub mod capture;
 pub mod client;
+pub mod codec;
 mod communication;
+pub mod config;
+pub mod congestion;
+mod decoder;
+pub mod detection;
+pub mod fec;
+pub mod impairment;
+mod keepalive;
 pub mod md5;
-mod network;
+pub mod network;
+pub mod options;
+mod piggyback;
+pub mod rto;
 pub mod server;
+mod session;
+pub mod state;
 mod sync_read;
 pub mod xfer;
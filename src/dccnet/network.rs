@@ -1,16 +1,13 @@
-use std::fmt;
+use std::{fmt, io, ops};
+
+use super::arq::Arq;
+use super::{detection::Detection, fec::Fec};
 
 const SYNC: u32 = 0xDCC023C2;
 pub const START_ID: u16 = 0;
-pub const FLAG_ACK: u8 = 0x80;
-pub const FLAG_END: u8 = 0x40;
-pub const FLAG_RST: u8 = 0x20;
-pub const _FLAG_ZER: u8 = 0x3F;
-pub const FLAG_SED: u8 = 0x0;
-pub const MAX_DATA_SIZE: usize = 0x400;
+pub const MAX_DATA_SIZE: usize = 0x1000;
+pub const MAX_MTU: usize = u16::MAX as usize;
 pub const PAYLOAD_HEADER_SIZE: usize = 15;
-pub const MAX_PAYLOAD_SIZE: usize = MAX_DATA_SIZE + PAYLOAD_HEADER_SIZE;
-pub const MAX_SEND_ATTEMPTS: usize = 16;
 
 #[derive(Debug, PartialEq)]
 #[allow(dead_code)]
@@ -19,6 +16,8 @@ pub enum PayloadErrorKind {
     InvalidSync,
     InvalidData,
     ChecksumMismatch,
+    InvalidFlags,
+    Io,
     Other,
 }
 
@@ -28,6 +27,7 @@ impl fmt::Display for PayloadErrorKind {
     }
 }
 
+#[derive(Debug)]
 pub struct PayloadError {
     pub kind: PayloadErrorKind,
     pub message: String,
@@ -48,6 +48,213 @@ impl fmt::Display for PayloadError {
     }
 }
 
+impl std::error::Error for PayloadError {}
+
+impl From<io::Error> for PayloadError {
+    fn from(e: io::Error) -> Self {
+        PayloadError::new(PayloadErrorKind::Io, &e.to_string())
+    }
+}
+
+/// Wire options shared by both ends of a connection.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameFormat {
+    /// Largest amount of data sent or accepted in a single frame.
+    pub mtu: usize,
+    /// Exchange MTUs with the peer at connection start and use the smaller one.
+    pub negotiate_mtu: bool,
+    /// Reject frames with reserved flag bits set, or extension bits this format
+    /// does not enable.
+    pub strict_flags: bool,
+    pub detection: Detection,
+    /// Forward error correction applied to the data region.
+    pub fec: Option<Fec>,
+    pub arq: Arq,
+    /// Let ACKs ride on outgoing data frames, with the acknowledged id in front
+    /// of the data.
+    pub piggyback: bool,
+    /// Answer data frames that fail their checksum with a NAK of their id.
+    pub nak: bool,
+    /// Advertise in ACKs how many more data frames the receive queue can take.
+    pub flow_control: bool,
+    /// Acknowledge END and retransmit it until acknowledged, instead of sending
+    /// it once as the specification does.
+    pub end_ack: bool,
+}
+
+impl Default for FrameFormat {
+    fn default() -> Self {
+        Self {
+            mtu: MAX_DATA_SIZE,
+            negotiate_mtu: false,
+            strict_flags: false,
+            detection: Detection::default(),
+            fec: None,
+            arq: Arq::default(),
+            piggyback: false,
+            nak: false,
+            flow_control: false,
+            end_ack: false,
+        }
+    }
+}
+
+impl FrameFormat {
+    /// Flags a frame may carry with the extensions this format enables. OPT is
+    /// always among them, as keepalives are understood whatever the format.
+    pub fn flags(&self) -> Flags {
+        let mut flags = Flags::ACK | Flags::END | Flags::RST | Flags::OPT;
+        if self.nak {
+            flags |= Flags::NAK | Flags::COPY;
+        }
+        if self.piggyback {
+            flags |= Flags::PIGGYBACK;
+        }
+        flags
+    }
+
+    /// Bytes the data region occupies on the wire once FEC parity is added.
+    pub fn data_size(&self, length: usize) -> usize {
+        match self.fec {
+            Some(fec) => fec.encoded_len(length),
+            None => length,
+        }
+    }
+
+    /// Bytes a frame with `length` bytes of data occupies on the wire.
+    pub fn frame_size(&self, length: usize) -> usize {
+        PAYLOAD_HEADER_SIZE + self.data_size(length) + self.detection.trailer_len()
+    }
+}
+
+/// Control bits carried in the flags byte of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Flags(u8);
+
+impl Flags {
+    pub const NONE: Flags = Flags(0x00);
+    pub const ACK: Flags = Flags(0x80);
+    pub const END: Flags = Flags(0x40);
+    pub const RST: Flags = Flags(0x20);
+    /// Extension: the data holds type-length-value options instead of user data.
+    pub const OPT: Flags = Flags(0x10);
+    /// Extension: the frame with this id arrived corrupted and should be resent.
+    pub const NAK: Flags = Flags(0x08);
+    /// Extension: the first two data bytes acknowledge the frame with that id.
+    pub const PIGGYBACK: Flags = Flags(0x04);
+    /// Extension: set on every other transmission of a data frame and echoed by
+    /// a NAK, telling which copy of the frame the NAK refers to.
+    pub const COPY: Flags = Flags(0x02);
+    pub const KNOWN: Flags = Flags(
+        Self::ACK.0
+            | Self::END.0
+            | Self::RST.0
+            | Self::OPT.0
+            | Self::NAK.0
+            | Self::PIGGYBACK.0
+            | Self::COPY.0,
+    );
+    pub const RESERVED: Flags = Flags(!Self::KNOWN.0);
+
+    const NAMES: [(Flags, &'static str); 7] = [
+        (Self::ACK, "ACK"),
+        (Self::END, "END"),
+        (Self::RST, "RST"),
+        (Self::OPT, "OPT"),
+        (Self::NAK, "NAK"),
+        (Self::PIGGYBACK, "PIGGYBACK"),
+        (Self::COPY, "COPY"),
+    ];
+
+    /// Keeps every bit, including reserved ones.
+    pub const fn from_bits(bits: u8) -> Self {
+        Flags(bits)
+    }
+
+    /// Rejects flags with any bit set outside `allowed`, such as a reserved bit
+    /// or an extension the format does not enable.
+    pub fn from_bits_strict(bits: u8, allowed: Flags) -> Result<Self, PayloadError> {
+        let flags = Flags(bits);
+        let unexpected = flags.difference(allowed);
+        if !unexpected.is_empty() {
+            return Err(PayloadError::new(
+                PayloadErrorKind::InvalidFlags,
+                &format!("Unexpected flag bits set: {}", unexpected),
+            ));
+        }
+
+        Ok(flags)
+    }
+
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub const fn contains(self, other: Flags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn intersects(self, other: Flags) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    /// These flags with the bits of `other` cleared.
+    pub const fn difference(self, other: Flags) -> Flags {
+        Flags(self.0 & !other.0)
+    }
+
+    pub const fn has_reserved(self) -> bool {
+        self.intersects(Self::RESERVED)
+    }
+}
+
+impl ops::BitOr for Flags {
+    type Output = Flags;
+
+    fn bitor(self, rhs: Flags) -> Flags {
+        Flags(self.0 | rhs.0)
+    }
+}
+
+impl ops::BitOrAssign for Flags {
+    fn bitor_assign(&mut self, rhs: Flags) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl ops::BitAnd for Flags {
+    type Output = Flags;
+
+    fn bitand(self, rhs: Flags) -> Flags {
+        Flags(self.0 & rhs.0)
+    }
+}
+
+impl fmt::Display for Flags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "NONE");
+        }
+
+        let mut names: Vec<String> = Self::NAMES
+            .iter()
+            .filter(|(flag, _)| self.contains(*flag))
+            .map(|(_, name)| name.to_string())
+            .collect();
+
+        let reserved = self.0 & Self::RESERVED.0;
+        if reserved != 0 {
+            names.push(format!("0x{:02X}", reserved));
+        }
+
+        write!(f, "{}", names.join("|"))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Payload {
     pub f_sync: u32,
@@ -55,36 +262,16 @@ pub struct Payload {
     pub chksum: u16,
     pub length: u16,
     pub id: u16,
-    pub flag: u8,
+    pub flag: Flags,
     pub data: Vec<u8>,
 }
 
 impl Payload {
-    fn checksum(frame: &[u8]) -> u16 {
-        let mut sum: u32 = 0;
-
-        // Iterate over the bytes two at a time (16 bits)
-        let mut i = 0;
-        while i < frame.len() {
-            let word = if i + 1 < frame.len() {
-                u16::from_be_bytes([frame[i], frame[i + 1]])
-            } else {
-                u16::from_be_bytes([frame[i], 0]) // Padding if odd number of bytes
-            };
-            sum += word as u32;
-
-            // Carry-around addition
-            if sum > 0xFFFF {
-                sum = (sum & 0xFFFF) + (sum >> 16);
-            }
-            i += 2;
-        }
-
-        // One's complement of the result
-        !(sum as u16)
+    pub fn new(data: Vec<u8>, id: u16, flag: Flags) -> Self {
+        Payload::with_format(data, id, flag, &FrameFormat::default())
     }
 
-    pub fn new(data: Vec<u8>, id: u16, flag: u8) -> Self {
+    pub fn with_format(data: Vec<u8>, id: u16, flag: Flags, format: &FrameFormat) -> Self {
         let mut payload = Self {
             f_sync: SYNC,
             s_sync: SYNC,
@@ -95,11 +282,36 @@ impl Payload {
             data,
         };
 
-        payload.chksum = Payload::checksum(&payload.as_bytes_without_checksum());
+        let code = payload.code(format);
+        payload.chksum = u16::from_be_bytes([code[0], code[1]]);
         payload
     }
 
     pub fn from_bytes(payload: &[u8]) -> Result<Self, PayloadError> {
+        Payload::from_bytes_with(payload, &FrameFormat::default())
+    }
+
+    /// Like `from_bytes`, but also rejects frames with reserved flag bits or
+    /// extension bits the default format does not enable.
+    pub fn from_bytes_strict(payload: &[u8]) -> Result<Self, PayloadError> {
+        let format = FrameFormat {
+            strict_flags: true,
+            ..FrameFormat::default()
+        };
+
+        Payload::from_bytes_with(payload, &format)
+    }
+
+    pub fn from_bytes_with(payload: &[u8], format: &FrameFormat) -> Result<Self, PayloadError> {
+        Payload::from_bytes_corrected(payload, format).map(|(payload, _)| payload)
+    }
+
+    /// Parses a frame, returning it together with the number of data bytes
+    /// repaired by FEC before the checksum was checked.
+    pub fn from_bytes_corrected(
+        payload: &[u8],
+        format: &FrameFormat,
+    ) -> Result<(Self, usize), PayloadError> {
         if payload.len() < PAYLOAD_HEADER_SIZE {
             return Err(PayloadError::new(
                 PayloadErrorKind::InvalidPayload,
@@ -107,12 +319,12 @@ impl Payload {
             ));
         }
 
-        let f_sync = u32::from_be_bytes(<[u8; 4]>::try_from(&payload[..4]).unwrap());
-        let s_sync = u32::from_be_bytes(<[u8; 4]>::try_from(&payload[4..8]).unwrap());
-        let chksum = u16::from_be_bytes(<[u8; 2]>::try_from(&payload[8..10]).unwrap());
-        let length = u16::from_be_bytes(<[u8; 2]>::try_from(&payload[10..12]).unwrap());
-        let id = u16::from_be_bytes(<[u8; 2]>::try_from(&payload[12..14]).unwrap());
-        let flag = u8::from_be_bytes(<[u8; 1]>::try_from(&payload[14..15]).unwrap());
+        let f_sync = u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]);
+        let s_sync = u32::from_be_bytes([payload[4], payload[5], payload[6], payload[7]]);
+        let chksum = u16::from_be_bytes([payload[8], payload[9]]);
+        let length = u16::from_be_bytes([payload[10], payload[11]]);
+        let id = u16::from_be_bytes([payload[12], payload[13]]);
+        let flag = Flags::from_bits(payload[14]);
 
         if f_sync != SYNC || s_sync != SYNC || f_sync != s_sync {
             return Err(PayloadError::new(
@@ -121,14 +333,20 @@ impl Payload {
             ));
         }
 
-        if (PAYLOAD_HEADER_SIZE + length as usize) > payload.len() {
+        let data_end = PAYLOAD_HEADER_SIZE + format.data_size(length as usize);
+        let frame_end = format.frame_size(length as usize);
+        if frame_end > payload.len() {
             return Err(PayloadError::new(
                 PayloadErrorKind::InvalidData,
                 "Insufficient data length",
             ));
         }
 
-        let data = payload[PAYLOAD_HEADER_SIZE..PAYLOAD_HEADER_SIZE + length as usize].to_vec();
+        let (data, corrected) = match format.fec {
+            Some(fec) => fec.decode(&payload[PAYLOAD_HEADER_SIZE..data_end]),
+            None => (payload[PAYLOAD_HEADER_SIZE..data_end].to_vec(), 0),
+        };
+        let trailer = &payload[data_end..frame_end];
         let payload = Self {
             f_sync,
             s_sync,
@@ -139,26 +357,43 @@ impl Payload {
             data,
         };
 
-        if !Payload::is_valid_payload_checksum(&payload) {
+        if !payload.is_valid_code(format, trailer) {
             return Err(PayloadError::new(
                 PayloadErrorKind::ChecksumMismatch,
                 "Passed checksum is incorrect",
             ));
         }
 
-        Ok(payload)
+        if format.strict_flags {
+            Flags::from_bits_strict(flag.bits(), format.flags())?;
+        }
+
+        Ok((payload, corrected))
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
+        self.as_bytes_with(&FrameFormat::default())
+    }
+
+    /// Encodes the frame with its code computed for `format`, which may differ
+    /// from the format the stored `chksum` was computed for.
+    pub fn as_bytes_with(&self, format: &FrameFormat) -> Vec<u8> {
+        let code = self.code(format);
         let mut bytes = Vec::new();
 
         bytes.extend_from_slice(&self.f_sync.to_be_bytes());
         bytes.extend_from_slice(&self.s_sync.to_be_bytes());
-        bytes.extend_from_slice(&self.chksum.to_be_bytes());
+        bytes.extend_from_slice(&code[..2]);
         bytes.extend_from_slice(&self.length.to_be_bytes());
         bytes.extend_from_slice(&self.id.to_be_bytes());
-        bytes.extend_from_slice(&self.flag.to_be_bytes());
-        bytes.extend_from_slice(&self.data);
+        bytes.push(self.flag.bits());
+
+        match format.fec {
+            Some(fec) => bytes.extend_from_slice(&fec.encode(&self.data)),
+            None => bytes.extend_from_slice(&self.data),
+        }
+
+        bytes.extend_from_slice(&code[2..]);
 
         bytes
     }
@@ -171,14 +406,22 @@ impl Payload {
         bytes.extend_from_slice(&0u16.to_be_bytes());
         bytes.extend_from_slice(&self.length.to_be_bytes());
         bytes.extend_from_slice(&self.id.to_be_bytes());
-        bytes.extend_from_slice(&self.flag.to_be_bytes());
+        bytes.push(self.flag.bits());
         bytes.extend_from_slice(&self.data);
 
         bytes
     }
 
-    fn is_valid_payload_checksum(payload: &Payload) -> bool {
-        payload.chksum == Payload::checksum(&payload.as_bytes_without_checksum())
+    fn code(&self, format: &FrameFormat) -> Vec<u8> {
+        format
+            .detection
+            .detector()
+            .code_bytes(&self.as_bytes_without_checksum())
+    }
+
+    fn is_valid_code(&self, format: &FrameFormat, trailer: &[u8]) -> bool {
+        let code = self.code(format);
+        self.chksum.to_be_bytes() == code[..2] && trailer == &code[2..]
     }
 }
 
@@ -190,7 +433,7 @@ impl fmt::Display for Payload {
         write!(f, " chksum: 0x{:04X},", self.chksum)?;
         write!(f, " length: {:0>4},", self.length)?;
         write!(f, " id: {},", self.id)?;
-        write!(f, " flag: 0x{:02X},", self.flag)?;
+        write!(f, " flag: {},", self.flag)?;
         write!(f, " data: [frame data]")?;
 
         // write!(f, " data: ")?;
@@ -201,3 +444,58 @@ impl fmt::Display for Payload {
         write!(f, " }}")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_display_names_each_bit() {
+        assert_eq!(Flags::NONE.to_string(), "NONE");
+        assert_eq!((Flags::ACK | Flags::END).to_string(), "ACK|END");
+        assert_eq!((Flags::NAK | Flags::from_bits(0x01)).to_string(), "NAK|0x01");
+    }
+
+    #[test]
+    fn strict_flags_reject_reserved_bits() {
+        let format = FrameFormat::default();
+        assert!(Flags::from_bits_strict(0x01, format.flags()).is_err());
+        assert_eq!(
+            Flags::from_bits_strict(0xC0, format.flags()).unwrap(),
+            Flags::ACK | Flags::END
+        );
+    }
+
+    #[test]
+    fn strict_flags_accept_only_enabled_extensions() {
+        let plain = FrameFormat::default();
+        for flag in [Flags::NAK, Flags::COPY, Flags::PIGGYBACK] {
+            let error = Flags::from_bits_strict(flag.bits(), plain.flags()).unwrap_err();
+            assert_eq!(error.kind, PayloadErrorKind::InvalidFlags);
+        }
+        assert!(Flags::from_bits_strict(Flags::OPT.bits(), plain.flags()).is_ok());
+
+        let extended = FrameFormat {
+            nak: true,
+            piggyback: true,
+            ..FrameFormat::default()
+        };
+        for flag in [Flags::NAK, Flags::COPY, Flags::PIGGYBACK] {
+            assert_eq!(Flags::from_bits_strict(flag.bits(), extended.flags()).unwrap(), flag);
+        }
+    }
+
+    #[test]
+    fn strict_format_rejects_frame_with_disabled_extension() {
+        let format = FrameFormat {
+            strict_flags: true,
+            ..FrameFormat::default()
+        };
+        let bytes = Payload::new(vec![1, 2, 3], 0, Flags::NAK).as_bytes_with(&format);
+        let error = Payload::from_bytes_with(&bytes, &format).unwrap_err();
+        assert_eq!(error.kind, PayloadErrorKind::InvalidFlags);
+
+        let nak = FrameFormat { nak: true, ..format };
+        assert!(Payload::from_bytes_with(&bytes, &nak).is_ok());
+    }
+}
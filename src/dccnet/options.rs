@@ -0,0 +1,101 @@
+/// Type-length-value options carried in the data of OPT frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameOption {
+    /// Largest amount of data the sender accepts in a single frame.
+    Mtu(u16),
+    /// Sent while the sender has nothing else to say, to show it is alive.
+    Keepalive,
+    /// Data frames the sender of an ACK can still take beyond the acknowledged one.
+    Window(u16),
+}
+
+const OPTION_MTU: u8 = 0x01;
+const OPTION_KEEPALIVE: u8 = 0x02;
+const OPTION_WINDOW: u8 = 0x03;
+
+impl FrameOption {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        match self {
+            FrameOption::Mtu(mtu) => {
+                bytes.extend_from_slice(&[OPTION_MTU, 2]);
+                bytes.extend_from_slice(&mtu.to_be_bytes());
+            }
+            FrameOption::Keepalive => bytes.extend_from_slice(&[OPTION_KEEPALIVE, 0]),
+            FrameOption::Window(window) => {
+                bytes.extend_from_slice(&[OPTION_WINDOW, 2]);
+                bytes.extend_from_slice(&window.to_be_bytes());
+            }
+        }
+    }
+
+    fn decode(kind: u8, value: &[u8]) -> Option<Self> {
+        match (kind, value) {
+            (OPTION_MTU, [high, low]) => Some(FrameOption::Mtu(u16::from_be_bytes([*high, *low]))),
+            (OPTION_KEEPALIVE, []) => Some(FrameOption::Keepalive),
+            (OPTION_WINDOW, [high, low]) => {
+                Some(FrameOption::Window(u16::from_be_bytes([*high, *low])))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn encode_all(options: &[FrameOption]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for option in options {
+            option.encode(&mut bytes);
+        }
+
+        bytes
+    }
+
+    /// Unknown or malformed options are skipped so newer peers stay compatible.
+    pub fn decode_all(data: &[u8]) -> Vec<FrameOption> {
+        let mut options = Vec::new();
+        let mut rest = data;
+
+        while let [kind, len, tail @ ..] = rest {
+            let len = *len as usize;
+            if tail.len() < len {
+                break;
+            }
+
+            if let Some(option) = FrameOption::decode(*kind, &tail[..len]) {
+                options.push(option);
+            }
+            rest = &tail[len..];
+        }
+
+        options
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn options_round_trip() {
+        let options = [
+            FrameOption::Mtu(1500),
+            FrameOption::Keepalive,
+            FrameOption::Window(7),
+        ];
+        let bytes = FrameOption::encode_all(&options);
+        assert_eq!(bytes, [0x01, 2, 0x05, 0xDC, 0x02, 0, 0x03, 2, 0, 7]);
+        assert_eq!(FrameOption::decode_all(&bytes), options);
+    }
+
+    #[test]
+    fn unknown_and_malformed_options_are_skipped() {
+        // An unknown type, an MTU of the wrong length, then a valid window
+        let bytes = [0x7F, 1, 0xAA, 0x01, 1, 0x05, 0x03, 2, 0, 9];
+        assert_eq!(FrameOption::decode_all(&bytes), [FrameOption::Window(9)]);
+    }
+
+    #[test]
+    fn truncated_option_ends_decoding() {
+        let mut bytes = FrameOption::encode_all(&[FrameOption::Keepalive]);
+        bytes.extend_from_slice(&[0x01, 2, 0x05]);
+        assert_eq!(FrameOption::decode_all(&bytes), [FrameOption::Keepalive]);
+    }
+}
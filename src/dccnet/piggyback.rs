@@ -0,0 +1,88 @@
+use super::network::{Flags, FrameFormat, Payload};
+
+/// Bytes at the start of a piggybacked frame's data holding the acknowledged id.
+pub const ACK_ID_SIZE: usize = 2;
+
+/// Whether a data frame has room for an ACK within the MTU.
+pub fn fits(payload: &Payload, format: &FrameFormat) -> bool {
+    payload.flag.difference(Flags::COPY).is_empty()
+        && !payload.data.is_empty()
+        && payload.data.len() + ACK_ID_SIZE <= format.mtu
+}
+
+/// The data frame with the ACK of `ack_id` riding on it: the PIGGYBACK flag is
+/// set, next to the copy bit if any, and the acknowledged id precedes the data.
+pub fn attach(payload: &Payload, ack_id: u16, format: &FrameFormat) -> Payload {
+    let mut data = Vec::with_capacity(ACK_ID_SIZE + payload.data.len());
+    data.extend_from_slice(&ack_id.to_be_bytes());
+    data.extend_from_slice(&payload.data);
+
+    let flag = Flags::PIGGYBACK | (payload.flag & Flags::COPY);
+    Payload::with_format(data, payload.id, flag, format)
+}
+
+/// Separates a frame built by [`attach`] into its ACK and its data frame.
+pub fn split(payload: &Payload) -> Option<(Payload, Payload)> {
+    if payload.flag.difference(Flags::COPY) != Flags::PIGGYBACK || payload.data.len() <= ACK_ID_SIZE {
+        return None;
+    }
+
+    let (ack_id, data) = payload.data.split_at(ACK_ID_SIZE);
+    let ack_id = u16::from_be_bytes([ack_id[0], ack_id[1]]);
+
+    Some((
+        Payload::new(vec![], ack_id, Flags::ACK),
+        Payload::new(data.to_vec(), payload.id, Flags::NONE),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(mtu: usize) -> FrameFormat {
+        FrameFormat {
+            mtu,
+            piggyback: true,
+            ..FrameFormat::default()
+        }
+    }
+
+    #[test]
+    fn attached_ack_splits_back_off() {
+        let format = format(64);
+        let payload = Payload::with_format(vec![1, 2, 3], 5, Flags::COPY, &format);
+
+        let combined = attach(&payload, 0xABCD, &format);
+        assert_eq!(combined.flag, Flags::PIGGYBACK | Flags::COPY);
+        assert_eq!(combined.data, [0xAB, 0xCD, 1, 2, 3]);
+
+        let (ack, data) = split(&combined).unwrap();
+        assert_eq!((ack.id, ack.flag), (0xABCD, Flags::ACK));
+        assert_eq!((data.id, data.flag), (5, Flags::NONE));
+        assert_eq!(data.data, payload.data);
+    }
+
+    #[test]
+    fn fits_leaves_room_for_the_ack_id() {
+        let format = format(10);
+        assert!(fits(&Payload::new(vec![0; 8], 0, Flags::NONE), &format));
+        assert!(!fits(&Payload::new(vec![0; 9], 0, Flags::NONE), &format));
+    }
+
+    #[test]
+    fn only_plain_data_frames_carry_acks() {
+        let format = format(10);
+        assert!(fits(&Payload::new(vec![0; 4], 0, Flags::COPY), &format));
+        assert!(!fits(&Payload::new(vec![0; 4], 0, Flags::END), &format));
+        assert!(!fits(&Payload::new(vec![], 0, Flags::NONE), &format));
+    }
+
+    #[test]
+    fn split_needs_the_piggyback_flag_and_data() {
+        assert!(split(&Payload::new(vec![0, 1, 2], 0, Flags::NONE)).is_none());
+        let ended = Payload::new(vec![0, 1, 2], 0, Flags::PIGGYBACK | Flags::END);
+        assert!(split(&ended).is_none());
+        assert!(split(&Payload::new(vec![0, 1], 0, Flags::PIGGYBACK)).is_none());
+    }
+}
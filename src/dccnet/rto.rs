@@ -0,0 +1,146 @@
+use std::time::Duration;
+
+/// Clock granularity added to the variance term, as in RFC 6298.
+const GRANULARITY: Duration = Duration::from_millis(1);
+
+/// Retransmission timeout derived from round-trip time samples with the
+/// Jacobson/Karels estimator (RFC 6298).
+///
+/// Samples must only come from frames acknowledged on their first transmission
+/// (Karn's rule): the ACK of a retransmitted frame cannot be matched to a send.
+/// [`RtoEstimator::acknowledged`] applies the rule.
+#[derive(Debug, Clone, Copy)]
+pub struct RtoEstimator {
+    srtt: Option<Duration>,
+    rttvar: Duration,
+    rto: Duration,
+    min_rto: Duration,
+    max_rto: Duration,
+}
+
+impl RtoEstimator {
+    pub fn new(initial_rto: Duration, min_rto: Duration, max_rto: Duration) -> Self {
+        Self {
+            srtt: None,
+            rttvar: Duration::ZERO,
+            rto: initial_rto.clamp(min_rto, max_rto),
+            min_rto,
+            max_rto,
+        }
+    }
+
+    pub fn rto(&self) -> Duration {
+        self.rto
+    }
+
+    /// Smoothed round-trip time, once at least one sample was taken.
+    pub fn srtt(&self) -> Option<Duration> {
+        self.srtt
+    }
+
+    /// Takes the time a frame waited for its ACK as a sample, unless the frame
+    /// was transmitted more than once.
+    pub fn acknowledged(&mut self, rtt: Duration, transmissions: usize) {
+        if transmissions == 1 {
+            self.sample(rtt);
+        }
+    }
+
+    pub fn sample(&mut self, rtt: Duration) {
+        let srtt = match self.srtt {
+            None => {
+                self.rttvar = rtt / 2;
+                rtt
+            }
+            Some(srtt) => {
+                self.rttvar = self.rttvar * 3 / 4 + srtt.abs_diff(rtt) / 4;
+                srtt * 7 / 8 + rtt / 8
+            }
+        };
+
+        self.srtt = Some(srtt);
+        self.rto = (srtt + GRANULARITY.max(self.rttvar * 4)).clamp(self.min_rto, self.max_rto);
+    }
+
+    /// Doubles the timeout after a frame went unacknowledged. The backed off
+    /// value is kept until the next valid sample.
+    pub fn back_off(&mut self) {
+        self.rto = (self.rto * 2).min(self.max_rto);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    fn estimator() -> RtoEstimator {
+        RtoEstimator::new(ms(1000), ms(20), ms(10_000))
+    }
+
+    #[test]
+    fn first_sample_sets_srtt_and_half_as_variance() {
+        let mut rto = estimator();
+        assert_eq!(rto.rto(), ms(1000));
+        assert_eq!(rto.srtt(), None);
+
+        rto.sample(ms(100));
+        assert_eq!(rto.srtt(), Some(ms(100)));
+        assert_eq!(rto.rttvar, ms(50));
+        // SRTT + 4 * RTTVAR
+        assert_eq!(rto.rto(), ms(300));
+    }
+
+    #[test]
+    fn later_samples_are_smoothed() {
+        let mut rto = estimator();
+        rto.sample(ms(100));
+        rto.sample(ms(180));
+
+        // RTTVAR = 3/4 * 50 + 1/4 * |100 - 180|, SRTT = 7/8 * 100 + 1/8 * 180
+        assert_eq!(rto.rttvar, ms(57) + Duration::from_micros(500));
+        assert_eq!(rto.srtt(), Some(ms(110)));
+        assert_eq!(rto.rto(), ms(340));
+    }
+
+    #[test]
+    fn timeout_is_clamped() {
+        let mut rto = RtoEstimator::new(ms(5), ms(20), ms(200));
+        assert_eq!(rto.rto(), ms(20));
+
+        rto.sample(ms(1));
+        assert_eq!(rto.rto(), ms(20));
+
+        rto.sample(ms(500));
+        assert_eq!(rto.rto(), ms(200));
+    }
+
+    #[test]
+    fn back_off_doubles_up_to_the_maximum() {
+        let mut rto = RtoEstimator::new(ms(300), ms(20), ms(1000));
+        rto.back_off();
+        assert_eq!(rto.rto(), ms(600));
+        rto.back_off();
+        assert_eq!(rto.rto(), ms(1000));
+
+        // Kept until the next sample
+        rto.sample(ms(100));
+        assert_eq!(rto.rto(), ms(300));
+    }
+
+    #[test]
+    fn retransmitted_frames_give_no_sample() {
+        let mut rto = estimator();
+        rto.back_off();
+        rto.acknowledged(ms(100), 2);
+        assert_eq!(rto.srtt(), None);
+        assert_eq!(rto.rto(), ms(2000));
+
+        rto.acknowledged(ms(100), 1);
+        assert_eq!(rto.srtt(), Some(ms(100)));
+        assert_eq!(rto.rto(), ms(300));
+    }
+}
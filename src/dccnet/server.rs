@@ -3,9 +3,15 @@ use std::io::{BufReader, BufWriter, Error};
 
 use tokio::net::{TcpListener, TcpStream};
 
+use crate::dccnet::config::DccnetConfig;
 use crate::dccnet::xfer;
 
-pub async fn run_server(port: u16, mut input: BufReader<File>, mut output: BufWriter<File>) {
+pub async fn run_server(
+    port: u16,
+    mut input: BufReader<File>,
+    mut output: BufWriter<File>,
+    config: &DccnetConfig,
+) -> std::io::Result<()> {
     let listener = TcpListener::bind(format!("[::]:{}", port))
         .await
         .unwrap_or_else(|e| {
@@ -15,8 +21,11 @@ pub async fn run_server(port: u16, mut input: BufReader<File>, mut output: BufWr
     println!("Server listening on [::]:{}", port);
 
     match listener.accept().await {
-        Ok((stream, _)) => handle_incoming_stream(Ok(stream), &mut input, &mut output).await,
-        Err(e) => eprintln!("Failed to accept connection: {}", e),
+        Ok((stream, _)) => handle_incoming_stream(Ok(stream), &mut input, &mut output, config).await,
+        Err(e) => {
+            eprintln!("Failed to accept connection: {}", e);
+            Err(e)
+        }
     }
 }
 
@@ -24,10 +33,14 @@ async fn handle_incoming_stream(
     stream: Result<TcpStream, Error>,
     input: &mut BufReader<File>,
     output: &mut BufWriter<File>,
-) {
+    config: &DccnetConfig,
+) -> std::io::Result<()> {
     match stream {
-        Ok(stream) => handle_incoming_connection(stream, input, output).await,
-        Err(e) => eprintln!("Connection failed: {}", e),
+        Ok(stream) => handle_incoming_connection(stream, input, output, config).await,
+        Err(e) => {
+            eprintln!("Connection failed: {}", e);
+            Err(e)
+        }
     }
 }
 
@@ -35,17 +48,19 @@ async fn handle_incoming_connection(
     stream: TcpStream,
     input: &mut BufReader<File>,
     output: &mut BufWriter<File>,
-) {
+    config: &DccnetConfig,
+) -> std::io::Result<()> {
     let peer_addr = match stream.peer_addr() {
         Ok(addr) => addr,
         Err(e) => {
             eprintln!("Failed to get peer address: {}", e);
-            return;
+            return Err(e);
         }
     };
     println!("New connection from {}", peer_addr);
 
-    xfer::handle_connection(stream, input, output).await;
+    let state = xfer::handle_connection(stream, input, output, config).await;
 
     println!("Connection closed with {}", peer_addr);
+    xfer::check_closed(state)
 }
@@ -0,0 +1,274 @@
+use std::{
+    fmt,
+    sync::{
+        Arc, Mutex, MutexGuard,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use tokio::{
+    net::{TcpStream, tcp::OwnedReadHalf},
+    task::JoinHandle,
+};
+
+use super::{
+    ack::{AckPolicy, PendingAck},
+    communication::{FrameWriter, NetworkError, NetworkErrorKind},
+    arq::Arq,
+    config::DccnetConfig,
+    congestion::CongestionControl,
+    decoder::FrameDecoder,
+    impairment::BitErrors,
+    keepalive,
+    network::Payload,
+    rto::RtoEstimator,
+    state::{ConnectionEvent, StateMachine},
+    sync_read::{self, Inbox},
+};
+
+/// Counters describing how a connection went, reported when it ends.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SessionStats {
+    /// Received data frames dropped. Data frames wait for room in the receive
+    /// queue instead, so these are only copies of a frame already waiting.
+    pub dropped_data: usize,
+    /// ACK frames evicted from a full ACK queue.
+    pub dropped_acks: usize,
+    /// Current retransmission timeout.
+    pub rto: Duration,
+    /// Smoothed round-trip time, if any frame was acknowledged on its first transmission.
+    pub srtt: Option<Duration>,
+    /// Congestion window of the windowed ARQ modes, in frames.
+    pub cwnd: Option<usize>,
+    pub ack_policy: AckPolicy,
+    /// ACK frames sent on their own.
+    pub acks_sent: usize,
+    /// ACKs that rode on a data frame.
+    pub acks_piggybacked: usize,
+    /// Bits flipped in the bytes written and read, if bit errors are injected.
+    pub flipped_out: Option<usize>,
+    pub flipped_in: Option<usize>,
+    /// Received data bytes repaired by FEC, if enabled.
+    pub fec_corrected: Option<usize>,
+}
+
+impl fmt::Display for SessionStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "dropped data frames: {}, dropped ACKs: {}, RTO: {} ms",
+            self.dropped_data,
+            self.dropped_acks,
+            self.rto.as_millis()
+        )?;
+
+        if let Some(srtt) = self.srtt {
+            write!(f, ", SRTT: {} us", srtt.as_micros())?;
+        }
+
+        if let Some(cwnd) = self.cwnd {
+            write!(f, ", CWND: {} frames", cwnd)?;
+        }
+
+        write!(
+            f,
+            ", ACK policy: {}, ACKs sent: {}, piggybacked: {}",
+            self.ack_policy, self.acks_sent, self.acks_piggybacked
+        )?;
+
+        if let Some(flipped) = self.flipped_out {
+            write!(f, ", bits flipped out: {}", flipped)?;
+        }
+
+        if let Some(flipped) = self.flipped_in {
+            write!(f, ", bits flipped in: {}", flipped)?;
+        }
+
+        if let Some(corrected) = self.fec_corrected {
+            write!(f, ", FEC corrected: {} bytes", corrected)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// State of a single DCCNET connection: its write half, the frames delivered by
+/// its reader task and the task itself, which is stopped when the session drops.
+///
+/// Frames can be read directly with [`Session::read_payload`] until
+/// [`Session::start`] hands the read half over to the reader task.
+pub struct Session {
+    config: DccnetConfig,
+    writer: Arc<FrameWriter>,
+    reader: Option<(OwnedReadHalf, FrameDecoder)>,
+    /// Frame read directly that the reader task still has to process.
+    unread: Option<Payload>,
+    incoming_errors: Option<Arc<BitErrors>>,
+    fec_corrected: Arc<AtomicUsize>,
+    inbox: Arc<Inbox>,
+    state: Arc<StateMachine>,
+    rto: Mutex<RtoEstimator>,
+    congestion: Mutex<Box<dyn CongestionControl>>,
+    pending_ack: Arc<PendingAck>,
+    reader_task: Option<JoinHandle<()>>,
+    keepalive_task: Option<JoinHandle<()>>,
+}
+
+impl Session {
+    pub fn new(stream: TcpStream, config: DccnetConfig) -> Self {
+        let (read_half, write_half) = stream.into_split();
+        // A peer with the same settings must not give up on a silent connection
+        // while this end is still backing off
+        let max_rto = config
+            .max_rto
+            .min(config.silence_timeout() / 2)
+            .max(config.min_rto);
+        let decoder = FrameDecoder::new(config.format);
+
+        Self {
+            writer: Arc::new(FrameWriter::new(
+                write_half,
+                config.format,
+                config.capture.clone(),
+                config.impairment.outgoing(),
+            )),
+            fec_corrected: Arc::clone(decoder.corrected()),
+            reader: Some((read_half, decoder)),
+            unread: None,
+            incoming_errors: config.impairment.incoming().map(Arc::new),
+            // Room for the ACK of every frame in flight, since individual ACKs
+            // cannot be dropped without causing retransmissions
+            inbox: Arc::new(Inbox::new(
+                config.queue_depth.max(config.window),
+                config.queue_depth,
+            )),
+            state: Arc::new(StateMachine::default()),
+            rto: Mutex::new(RtoEstimator::new(
+                config.ack_timeout,
+                config.min_rto,
+                max_rto,
+            )),
+            congestion: Mutex::new(config.congestion.controller(config.window)),
+            pending_ack: Arc::default(),
+            reader_task: None,
+            keepalive_task: None,
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &DccnetConfig {
+        &self.config
+    }
+
+    pub fn writer(&self) -> &Arc<FrameWriter> {
+        &self.writer
+    }
+
+    pub fn inbox(&self) -> &Arc<Inbox> {
+        &self.inbox
+    }
+
+    pub fn state(&self) -> &StateMachine {
+        &self.state
+    }
+
+    /// The ACK held back by the ACK policy or waiting to ride on a data frame.
+    pub fn pending_ack(&self) -> &Arc<PendingAck> {
+        &self.pending_ack
+    }
+
+    pub fn rto(&self) -> MutexGuard<'_, RtoEstimator> {
+        self.rto.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn congestion(&self) -> MutexGuard<'_, Box<dyn CongestionControl>> {
+        self.congestion.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn stats(&self) -> SessionStats {
+        let rto = *self.rto();
+        let cwnd = match self.config.format.arq {
+            Arq::StopAndWait => None,
+            Arq::GoBackN | Arq::SelectiveRepeat => Some(self.congestion().window()),
+        };
+
+        SessionStats {
+            dropped_data: self.inbox.dropped_data(),
+            dropped_acks: self.inbox.dropped_acks(),
+            rto: rto.rto(),
+            srtt: rto.srtt(),
+            cwnd,
+            ack_policy: self.config.ack_policy,
+            acks_sent: self.pending_ack.sent(),
+            acks_piggybacked: self.pending_ack.piggybacked(),
+            flipped_out: self.writer.bit_errors().map(BitErrors::flipped),
+            flipped_in: self.incoming_errors.as_deref().map(BitErrors::flipped),
+            fec_corrected: self
+                .config
+                .format
+                .fec
+                .map(|_| self.fec_corrected.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// Reads the next frame from the stream before the reader task is started.
+    pub async fn read_payload(&mut self) -> Result<Payload, NetworkError> {
+        let Some((stream_read, decoder)) = self.reader.as_mut() else {
+            return Err(NetworkError::new(
+                NetworkErrorKind::Other,
+                "Session reader task already started",
+            ));
+        };
+
+        sync_read::read_next_payload(
+            stream_read,
+            decoder,
+            self.config.capture.as_deref(),
+            self.incoming_errors.as_deref(),
+            self.config.read_timeout,
+        )
+        .await
+    }
+
+    /// Hands a frame read with [`Session::read_payload`] over to the reader
+    /// task, which processes it first once started.
+    pub fn unread(&mut self, payload: Payload) {
+        self.unread = Some(payload);
+    }
+
+    /// Spawns the reader task that delivers ACK and data frames to the inbox,
+    /// and the keepalive task if enabled, and marks the connection as established.
+    pub fn start(&mut self) {
+        if let Some((stream_read, decoder)) = self.reader.take() {
+            self.reader_task = Some(sync_read::read_stream_data_loop(
+                stream_read,
+                decoder,
+                self.unread.take(),
+                self.config.clone(),
+                self.incoming_errors.clone(),
+                Arc::clone(&self.inbox),
+                Arc::clone(&self.state),
+            ));
+            if let Some(interval) = self.config.keepalive {
+                self.keepalive_task = Some(keepalive::keepalive_loop(
+                    Arc::clone(&self.writer),
+                    Arc::clone(&self.state),
+                    interval,
+                ));
+            }
+            let _ = self.state.apply(ConnectionEvent::Open);
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        for task in [self.reader_task.take(), self.keepalive_task.take()]
+            .into_iter()
+            .flatten()
+        {
+            task.abort();
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use std::fmt;
+
+use tokio::sync::watch;
+
+/// Lifecycle of a DCCNET connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Connected at the TCP level, options not yet exchanged.
+    Connecting,
+    /// Data flows in both directions.
+    Established,
+    /// This end sent END and only receives.
+    SendClosed,
+    /// The peer sent END and this end only sends.
+    RecvClosed,
+    /// Both ends sent END.
+    Closed,
+    /// Either end sent RST.
+    Reset,
+    /// The stream ended or went silent before both ends sent END.
+    Aborted,
+}
+
+/// Something that happened on a connection and may change its state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    /// The connection is ready to carry data.
+    Open,
+    /// END was sent.
+    SendEnd,
+    /// END was received.
+    RecvEnd,
+    /// RST was sent or received.
+    Rst,
+    /// The stream ended or the peer stayed silent for too long.
+    PeerLost,
+}
+
+impl ConnectionState {
+    pub fn on(self, event: ConnectionEvent) -> Result<ConnectionState, TransitionError> {
+        use ConnectionEvent::*;
+        use ConnectionState::*;
+
+        match (self, event) {
+            (Connecting, Open) => Ok(Established),
+            (Established, SendEnd) => Ok(SendClosed),
+            (Established, RecvEnd) => Ok(RecvClosed),
+            (SendClosed, RecvEnd) | (RecvClosed, SendEnd) => Ok(Closed),
+            (Connecting | Established | SendClosed | RecvClosed, Rst) => Ok(Reset),
+            (Connecting | Established | SendClosed | RecvClosed, PeerLost) => Ok(Aborted),
+            (state, event) => Err(TransitionError { state, event }),
+        }
+    }
+
+    pub fn can_send(self) -> bool {
+        matches!(self, ConnectionState::Established | ConnectionState::RecvClosed)
+    }
+
+    pub fn can_receive(self) -> bool {
+        matches!(self, ConnectionState::Established | ConnectionState::SendClosed)
+    }
+
+    /// Whether the connection is over, cleanly or not.
+    pub fn is_final(self) -> bool {
+        matches!(
+            self,
+            ConnectionState::Closed | ConnectionState::Reset | ConnectionState::Aborted
+        )
+    }
+}
+
+impl fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl fmt::Display for ConnectionEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// An event that is not allowed in the current state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransitionError {
+    pub state: ConnectionState,
+    pub event: ConnectionEvent,
+}
+
+impl fmt::Display for TransitionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid transition from {} on {}", self.state, self.event)
+    }
+}
+
+impl std::error::Error for TransitionError {}
+
+/// Connection state shared by the send and receive paths and the reader task,
+/// which can wait for it to change.
+pub struct StateMachine {
+    state: watch::Sender<ConnectionState>,
+}
+
+impl Default for StateMachine {
+    fn default() -> Self {
+        Self {
+            state: watch::Sender::new(ConnectionState::Connecting),
+        }
+    }
+}
+
+impl StateMachine {
+    pub fn state(&self) -> ConnectionState {
+        *self.state.borrow()
+    }
+
+    /// Applies the event and logs the transition, leaving the state untouched
+    /// if the transition is invalid.
+    pub fn apply(&self, event: ConnectionEvent) -> Result<ConnectionState, TransitionError> {
+        let mut result = Err(TransitionError {
+            state: self.state(),
+            event,
+        });
+
+        self.state.send_if_modified(|state| {
+            result = state.on(event);
+            match result {
+                Ok(next) => {
+                    println!("STATE    {} -> {} ({})", state, next, event);
+                    *state = next;
+                    true
+                }
+                Err(e) => {
+                    println!("STATE    {}", e);
+                    false
+                }
+            }
+        });
+
+        result
+    }
+
+    /// Resolves once the connection has been reset.
+    pub async fn reset(&self) {
+        let mut receiver = self.state.subscribe();
+        let _ = receiver.wait_for(|&state| state == ConnectionState::Reset).await;
+    }
+}
@@ -1,96 +1,348 @@
-use std::sync::Arc;
-
-use once_cell::sync::Lazy;
-use tokio::time::{Duration, sleep, timeout};
-use tokio::{io::AsyncReadExt, net::tcp::OwnedReadHalf, sync::Mutex};
+use std::{
+    collections::VecDeque,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+};
 
-const WAIT_ACK_TIMEOUT: u64 = 1000;
+use tokio::time::{Duration, timeout};
+use tokio::{
+    io::AsyncReadExt,
+    net::tcp::OwnedReadHalf,
+    sync::{Mutex, Notify},
+    task::JoinHandle,
+};
 
 use super::{
-    communication::{NetworkError, NetworkErrorKind},
-    network::{self, Payload},
+    capture::{Capture, Direction},
+    communication::{self, NetworkError, NetworkErrorKind},
+    config::DccnetConfig,
+    decoder::FrameDecoder,
+    impairment::BitErrors,
+    keepalive,
+    piggyback,
+    network::{Flags, Payload},
+    state::{ConnectionEvent, StateMachine},
 };
 
-static LAST_RECV_ACK: Lazy<Arc<Mutex<Option<Payload>>>> = Lazy::new(|| Arc::new(Mutex::new(None)));
-static LAST_RECV_DATA: Lazy<Arc<Mutex<Option<Payload>>>> = Lazy::new(|| Arc::new(Mutex::new(None)));
+/// A data frame handed to the receive path, or the id and copy bit of one that
+/// failed its checksum, which no frame read from the stream can pass for.
+#[derive(Clone, Debug)]
+pub enum Received {
+    Frame(Payload),
+    Corrupted { id: u16, copy: Flags },
+}
 
-async fn read_next_payload(stream_read: &Mutex<OwnedReadHalf>) -> Result<Payload, NetworkError> {
-    let mut buf = vec![0u8; network::MAX_PAYLOAD_SIZE];
+/// Bounded FIFO of frames handed from the reader task to the session.
+struct Queue<T> {
+    frames: Mutex<VecDeque<T>>,
+    depth: usize,
+    dropped: AtomicUsize,
+    /// Set once no more frames will be pushed.
+    closed: AtomicBool,
+    filled: Notify,
+    emptied: Notify,
+}
 
-    let read_result = timeout(Duration::from_secs(3), async {
-        let mut stream = stream_read.lock().await;
-        stream.read(&mut buf).await
-    })
-    .await;
+impl<T> Queue<T> {
+    fn new(depth: usize) -> Self {
+        Self {
+            frames: Mutex::new(VecDeque::with_capacity(depth)),
+            depth,
+            dropped: AtomicUsize::new(0),
+            closed: AtomicBool::new(false),
+            filled: Notify::new(),
+            emptied: Notify::new(),
+        }
+    }
 
-    let bytes_read = match read_result {
-        Ok(Ok(bytes)) => bytes,
-        Ok(Err(e)) => {
-            return Err(NetworkError::new(
-                NetworkErrorKind::ConnectionError,
-                &format!("Read error: {}", e),
-            ));
+    /// Appends the frame once there is room for it.
+    async fn push(&self, payload: T) {
+        loop {
+            let mut frames = self.frames.lock().await;
+            if frames.len() < self.depth {
+                frames.push_back(payload);
+                self.filled.notify_one();
+                return;
+            }
+            drop(frames);
+            self.emptied.notified().await;
         }
-        Err(_) => {
-            return Err(NetworkError::new(
-                NetworkErrorKind::TimeoutError,
-                "Read operation timed out",
-            ));
+    }
+
+    /// Appends the frame if there is room for it, or hands it back.
+    async fn try_push(&self, payload: T) -> Result<(), T> {
+        let mut frames = self.frames.lock().await;
+        if frames.len() >= self.depth {
+            return Err(payload);
         }
-    };
+        frames.push_back(payload);
+        self.filled.notify_one();
+        Ok(())
+    }
 
-    if bytes_read == 0 {
-        return Err(NetworkError::new(
-            NetworkErrorKind::ConnectionClosed,
-            "Connection closed by peer",
-        ));
+    /// Takes the oldest frame, waiting for one unless the queue was closed.
+    async fn pop(&self) -> Option<T> {
+        loop {
+            // Registered before checking, so neither a permit nor a close is missed
+            let filled = self.filled.notified();
+            tokio::pin!(filled);
+            filled.as_mut().enable();
+
+            if let Some(payload) = self.frames.lock().await.pop_front() {
+                self.emptied.notify_one();
+                return Some(payload);
+            }
+            if self.closed.load(Ordering::Acquire) {
+                return None;
+            }
+            filled.await;
+        }
     }
 
-    let payload = match Payload::from_bytes(&buf[..bytes_read]) {
-        Ok(p) => p,
-        Err(e) => {
+    /// Lets waiters take the frames left and then return empty-handed.
+    fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.filled.notify_waiters();
+    }
+
+    async fn is_empty(&self) -> bool {
+        self.frames.lock().await.is_empty()
+    }
+
+    async fn space(&self) -> usize {
+        self.depth.saturating_sub(self.frames.lock().await.len())
+    }
+
+    fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Queue<Payload> {
+    /// Appends the frame right away, evicting the oldest one if the queue is full.
+    async fn push_evicting(&self, payload: Payload) {
+        let mut frames = self.frames.lock().await;
+        if frames.len() >= self.depth {
+            if let Some(evicted) = frames.pop_front() {
+                println!("DROP     {evicted}");
+            }
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        frames.push_back(payload);
+        self.filled.notify_one();
+    }
+}
+
+/// ACK and data frames handed over by the reader task of one connection.
+pub struct Inbox {
+    ack: Queue<Payload>,
+    data: Queue<Received>,
+    /// Copies of data frames dropped while the frame waited for room, the only
+    /// data frames ever dropped.
+    dropped_data: AtomicUsize,
+}
+
+impl Inbox {
+    pub fn new(ack_depth: usize, data_depth: usize) -> Self {
+        Self {
+            ack: Queue::new(ack_depth),
+            data: Queue::new(data_depth),
+            dropped_data: AtomicUsize::new(0),
+        }
+    }
+
+    /// Whether the reader task stopped, so no more frames will arrive.
+    pub fn closed(&self) -> bool {
+        self.data.closed.load(Ordering::Acquire)
+    }
+
+    /// Lets the frames already delivered be read, and then fails every read.
+    fn close(&self) {
+        self.ack.close();
+        self.data.close();
+    }
+
+    /// Whether data frames are waiting to be read.
+    pub async fn has_data(&self) -> bool {
+        !self.data.is_empty().await
+    }
+
+    /// Data frames that can still be queued without waiting.
+    pub async fn data_space(&self) -> usize {
+        self.data.space().await
+    }
+
+    pub fn dropped_acks(&self) -> usize {
+        self.ack.dropped()
+    }
+
+    pub fn dropped_data(&self) -> usize {
+        self.dropped_data.load(Ordering::Relaxed)
+    }
+}
+
+pub async fn read_next_payload(
+    stream_read: &mut OwnedReadHalf,
+    decoder: &mut FrameDecoder,
+    capture: Option<&Capture>,
+    bit_errors: Option<&BitErrors>,
+    read_timeout: Duration,
+) -> Result<Payload, NetworkError> {
+    let mut buf = vec![0u8; decoder.format().frame_size(decoder.format().mtu)];
+
+    loop {
+        if let Some((result, raw)) = decoder.next_payload() {
+            // Rejected candidates are captured too, as they were read
+            if let Some(capture) = capture {
+                capture.record(Direction::Received, &raw).unwrap_or_else(|e| {
+                    eprintln!("Failed to capture frame: {}", e);
+                });
+            }
+
+            return result.map_err(|e| {
+                NetworkError::new(
+                    NetworkErrorKind::ProtocolError,
+                    &format!("Failed to parse payload: {}", e),
+                )
+            });
+        }
+
+        let read_result = timeout(read_timeout, stream_read.read(&mut buf)).await;
+
+        let bytes_read = match read_result {
+            Ok(Ok(bytes)) => bytes,
+            Ok(Err(e)) => {
+                return Err(NetworkError::new(
+                    NetworkErrorKind::ConnectionError,
+                    &format!("Read error: {}", e),
+                ));
+            }
+            Err(_) => {
+                return Err(NetworkError::new(
+                    NetworkErrorKind::TimeoutError,
+                    "Read operation timed out",
+                ));
+            }
+        };
+
+        if bytes_read == 0 {
             return Err(NetworkError::new(
-                NetworkErrorKind::ProtocolError,
-                &format!("Failed to parse payload: {}", e),
+                NetworkErrorKind::ConnectionClosed,
+                "Connection closed by peer",
             ));
         }
-    };
 
-    Ok(payload)
+        if let Some(bit_errors) = bit_errors {
+            bit_errors.apply(&mut buf[..bytes_read]);
+        }
+        decoder.extend(&buf[..bytes_read]);
+    }
 }
 
-pub async fn read_stream_data_loop(stream_read: Arc<Mutex<OwnedReadHalf>>) {
+/// Delivers the frames read from the stream to the inbox, starting with
+/// `first` if a frame was read before the task started.
+pub fn read_stream_data_loop(
+    mut stream_read: OwnedReadHalf,
+    mut decoder: FrameDecoder,
+    first: Option<Payload>,
+    config: DccnetConfig,
+    bit_errors: Option<Arc<BitErrors>>,
+    inbox: Arc<Inbox>,
+    state: Arc<StateMachine>,
+) -> JoinHandle<()> {
     tokio::spawn(async move {
+        let capture = config.capture.as_deref();
+        let read_timeout = config.silence_timeout();
+        // Data frames waiting for room in the queue, oldest first. Copies are
+        // discarded, so a whole window of the peer fits
+        let mut pending = VecDeque::new();
+        let hold = config.queue_depth.max(config.window);
+
+        if let Some(payload) = first {
+            let result = Ok(payload);
+            let _ =
+                process_payload(result, &mut decoder, &config, &inbox, &mut pending, &state).await;
+        }
+
         loop {
-            if let Err(should_break) = process_next_payload(&stream_read).await {
-                if should_break {
-                    break;
+            let next = pending.front().cloned();
+            let waiting = next.is_some();
+
+            // Keep reading while data frames wait, so ACKs still get through, up
+            // to `hold` of them, after which TCP slows the peer down
+            let result = tokio::select! {
+                biased;
+                _ = async { inbox.data.push(next.unwrap()).await }, if waiting => {
+                    pending.pop_front();
+                    continue;
                 }
+                result = read_next_payload(
+                    &mut stream_read,
+                    &mut decoder,
+                    capture,
+                    bit_errors.as_deref(),
+                    read_timeout,
+                ), if pending.len() < hold => result,
+            };
+
+            if let Err(should_break) =
+                process_payload(result, &mut decoder, &config, &inbox, &mut pending, &state).await
+                && should_break
+            {
+                break;
             }
         }
-    });
+
+        // Frames received before the stream ended are still delivered
+        for payload in pending {
+            inbox.data.push(payload).await;
+        }
+        inbox.close();
+    })
 }
 
-async fn process_next_payload(stream_read: &Arc<Mutex<OwnedReadHalf>>) -> Result<(), bool> {
-    match read_next_payload(stream_read).await {
+async fn process_payload(
+    result: Result<Payload, NetworkError>,
+    decoder: &mut FrameDecoder,
+    config: &DccnetConfig,
+    inbox: &Inbox,
+    pending: &mut VecDeque<Received>,
+    state: &StateMachine,
+) -> Result<(), bool> {
+    match result {
         Ok(payload) => {
-            handle_payload(payload).await;
+            // A piggybacked ACK is delivered as if it had come on its own
+            match piggyback::split(&payload).filter(|_| config.format.piggyback) {
+                Some((ack, data)) => {
+                    handle_payload(inbox, pending, state, ack, config).await;
+                    handle_payload(inbox, pending, state, data, config).await;
+                }
+                None => handle_payload(inbox, pending, state, payload, config).await,
+            }
             Ok(())
         }
-        Err(e) => handle_read_error(e).await,
+        Err(e) => {
+            // The receive path answers with a NAK, as only it can write
+            if config.format.nak
+                && let Some((id, copy)) = decoder.take_corrupted()
+            {
+                store_data(inbox, pending, Received::Corrupted { id, copy }).await;
+            }
+            handle_read_error(e)
+        }
     }
 }
 
-async fn handle_read_error(error: NetworkError) -> Result<(), bool> {
+fn handle_read_error(error: NetworkError) -> Result<(), bool> {
     match error.kind {
         NetworkErrorKind::TimeoutError => {
             println!("Timeout error: {}", error);
-            end_connection().await;
             Err(true)
         }
-        NetworkErrorKind::ConnectionClosed => {
+        NetworkErrorKind::ConnectionClosed | NetworkErrorKind::ConnectionError => {
             println!("Connection closed: {}", error);
-            end_connection().await;
             Err(true)
         }
         _ => {
@@ -100,62 +352,154 @@ async fn handle_read_error(error: NetworkError) -> Result<(), bool> {
     }
 }
 
-async fn end_connection() {
-    let payload = Payload::new(vec![], 0, network::FLAG_END);
+async fn handle_payload(
+    inbox: &Inbox,
+    pending: &mut VecDeque<Received>,
+    state: &StateMachine,
+    payload: Payload,
+    config: &DccnetConfig,
+) {
+    if keepalive::is_keepalive(&payload) {
+        println!("RECV KEEPALIVE");
+        return;
+    }
 
-    store_ack_payload(payload.clone()).await;
-    store_data_payload(payload).await;
-}
+    // A negotiating peer that missed the answer to its OPT sends it again,
+    // for the receive path to answer once more
+    if communication::negotiated_mtu(&payload).is_some() {
+        if config.format.negotiate_mtu && !payload.flag.contains(Flags::ACK) {
+            store_data(inbox, pending, Received::Frame(payload)).await;
+        }
+        return;
+    }
 
-async fn handle_payload(payload: Payload) {
-    match payload.flag {
-        network::FLAG_ACK => store_ack_payload(payload).await,
-        _ => store_data_payload(payload).await,
+    // Reset right away so a half that is not reading data frames stops as well
+    if communication::is_rst(&payload) {
+        let _ = state.apply(ConnectionEvent::Rst);
+    }
+
+    // NAKs are answers for the sender, just like ACKs
+    let answer = match config.format.nak {
+        true => Flags::ACK | Flags::NAK,
+        false => Flags::ACK,
+    };
+    if payload.flag.intersects(answer) {
+        inbox.ack.push_evicting(payload.clone()).await;
+    }
+
+    // A combined frame such as ACK|END still carries information for the receiver
+    if !payload.flag.intersects(answer) || payload.flag.intersects(Flags::END | Flags::RST) {
+        store_data(inbox, pending, Received::Frame(payload)).await;
     }
 }
 
-async fn store_ack_payload(payload: Payload) {
-    let mut last_ack = LAST_RECV_ACK.lock().await;
-    if last_ack.is_none() {
-        *last_ack = Some(payload);
+/// Queues a data frame without waiting, keeping it in `pending` behind the
+/// frames already there if the queue is full. A copy of a frame still pending
+/// is discarded, as the frame is acknowledged once the session reads it.
+async fn store_data(inbox: &Inbox, pending: &mut VecDeque<Received>, received: Received) {
+    if let Received::Frame(payload) = &received {
+        let same_frame = |waiting: &Received| match waiting {
+            Received::Frame(waiting) => {
+                waiting.id == payload.id
+                    && waiting.flag.difference(Flags::COPY) == payload.flag.difference(Flags::COPY)
+                    && waiting.data == payload.data
+            }
+            Received::Corrupted { .. } => false,
+        };
+        if pending.iter().any(same_frame) {
+            inbox.dropped_data.fetch_add(1, Ordering::Relaxed);
+            println!("DUPLICATE {payload}");
+            return;
+        }
+    }
+
+    if !pending.is_empty() {
+        pending.push_back(received);
+    } else if let Err(received) = inbox.data.try_push(received).await {
+        pending.push_back(received);
     }
 }
 
-async fn store_data_payload(payload: Payload) {
-    let mut last_data = LAST_RECV_DATA.lock().await;
-    if last_data.is_none() {
-        *last_data = Some(payload);
+/// Waits for the next ACK or NAK, failing with `ConnectionClosed` once the
+/// reader task stopped and every frame it delivered was taken.
+pub async fn read_stream_ack(inbox: &Inbox, ack_timeout: Duration) -> Result<Payload, NetworkError> {
+    match timeout(ack_timeout, inbox.ack.pop()).await {
+        Ok(Some(payload)) => Ok(payload),
+        Ok(None) => Err(communication::closed_error()),
+        Err(_) => Err(NetworkError::new(
+            NetworkErrorKind::TimeoutError,
+            &format!(
+                "No ACK payload available after waiting for {} ms",
+                ack_timeout.as_millis()
+            ),
+        )),
     }
 }
 
-pub async fn read_stream_ack() -> Result<Payload, NetworkError> {
-    let mut elapsed = 0;
-    let interval = Duration::from_millis(10);
+/// Waits for the next data frame, or returns `None` once the reader task
+/// stopped and every frame it delivered was taken.
+pub async fn read_stream_data(inbox: &Inbox) -> Option<Received> {
+    inbox.data.pop().await
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::AsyncWriteExt,
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::*;
+
+    fn data(id: u16) -> Payload {
+        Payload::new(vec![id as u8; 8], id, Flags::NONE)
+    }
 
-    while elapsed < WAIT_ACK_TIMEOUT {
-        if let Some(payload) = LAST_RECV_ACK.lock().await.take() {
-            return Ok(payload);
+    fn frame(received: Option<Received>) -> Payload {
+        match received {
+            Some(Received::Frame(payload)) => payload,
+            other => panic!("expected a data frame, got {:?}", other),
         }
-        sleep(interval).await;
-        elapsed += interval.as_millis() as u64;
     }
 
-    Err(NetworkError::new(
-        NetworkErrorKind::Other,
-        "No ACK payload available after waiting for 1 seconds",
-    ))
-}
+    /// With room for a single data frame, the reader task holds the next one
+    /// back, drops a copy of it and still delivers the ACK behind them.
+    #[tokio::test]
+    async fn acks_get_through_while_data_waits_for_room() {
+        let config = DccnetConfig {
+            queue_depth: 1,
+            window: 2,
+            ..DccnetConfig::default()
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut peer = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let (stream_read, _stream_write) = stream.into_split();
 
-pub async fn read_stream_data() -> Result<Payload, NetworkError> {
-    let payload = match LAST_RECV_DATA.lock().await.take() {
-        Some(p) => p,
-        None => {
-            return Err(NetworkError::new(
-                NetworkErrorKind::UnexpectedFlagError,
-                "No DATA payload available",
-            ));
+        let inbox = Arc::new(Inbox::new(config.queue_depth, config.queue_depth));
+        let task = read_stream_data_loop(
+            stream_read,
+            FrameDecoder::new(config.format),
+            None,
+            config.clone(),
+            None,
+            Arc::clone(&inbox),
+            Arc::new(StateMachine::default()),
+        );
+
+        for payload in [data(0), data(1), data(1), Payload::new(vec![], 7, Flags::ACK)] {
+            peer.write_all(&payload.as_bytes()).await.unwrap();
         }
-    };
+        let Ok(ack) = read_stream_ack(&inbox, Duration::from_secs(1)).await else {
+            panic!("the ACK is held back behind the data frames");
+        };
+        assert_eq!(ack.id, 7);
+        assert_eq!(inbox.dropped_data(), 1);
 
-    Ok(payload)
+        drop(peer);
+        assert_eq!(frame(read_stream_data(&inbox).await).id, 0);
+        assert_eq!(frame(read_stream_data(&inbox).await).id, 1);
+        assert!(read_stream_data(&inbox).await.is_none());
+        task.await.unwrap();
+    }
 }
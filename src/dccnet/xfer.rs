@@ -4,66 +4,132 @@ use std::{
     sync::Arc,
 };
 
-use tokio::{
-    io::AsyncWriteExt,
-    net::{TcpStream, tcp::OwnedWriteHalf},
-    sync::Mutex,
-};
+use tokio::{net::TcpStream, sync::Notify, time::sleep};
 
 use super::{
+    arq::{self, Arq, Receiver},
     communication::{self, NetworkErrorKind},
-    network::{self, Payload},
-    sync_read
+    config::DccnetConfig,
+    network::{self, Flags},
+    piggyback,
+    session::Session,
+    state::ConnectionState,
 };
 
-pub async fn handle_connection(stream: TcpStream, input: &mut BufReader<File>, output: &mut BufWriter<File>) {
-    let (reader_half, writer_half) = stream.into_split();
-    let reader_half_mutex =  Arc::new(Mutex::new(reader_half));
-    let writer_half_mutex = Arc::new(Mutex::new(writer_half));
-    
-    let future_send = handle_client_send( &writer_half_mutex, input);
-    let future_receive = handle_client_receive(&writer_half_mutex, output);
+/// Transfers `input` to the peer while writing what it sends to `output`, and
+/// returns the state the connection ended in.
+pub async fn handle_connection(
+    stream: TcpStream,
+    input: &mut BufReader<File>,
+    output: &mut BufWriter<File>,
+    config: &DccnetConfig,
+) -> ConnectionState {
+    let mut session = Session::new(stream, config.clone());
+
+    let mtu = if config.format.negotiate_mtu {
+        match communication::negotiate_mtu(&mut session).await {
+            Ok(mtu) => mtu,
+            Err(e) => {
+                eprintln!("Error negotiating MTU: {}", e);
+                communication::send_rst(&session, Some(e.to_string().into_bytes())).await;
+                return session.state().state();
+            }
+        }
+    } else {
+        config.format.mtu
+    };
+    println!("Using MTU of {} bytes", mtu);
+
+    // Leave room for the id of an ACK riding on each data frame
+    let data_mtu = match mtu.checked_sub(piggyback::ACK_ID_SIZE) {
+        Some(data_mtu) if config.format.piggyback && data_mtu > 0 => data_mtu,
+        _ => mtu,
+    };
+
+    session.start();
+    let session = Arc::new(session);
+
+    let send_done = Notify::new();
+    let future_send = async {
+        let result = handle_client_send(&session, input, data_mtu).await;
+        send_done.notify_one();
+        result
+    };
+    let future_receive = async {
+        let result = handle_client_receive(&session, output).await;
+
+        // With END-ACK the peer retransmits its END until it is acknowledged,
+        // so keep answering while this end is still sending and for a while after
+        if config.format.end_ack {
+            tokio::select! {
+                _ = communication::answer_end(&session) => {}
+                _ = async {
+                    send_done.notified().await;
+                    sleep(config.linger).await;
+                } => {}
+            }
+        }
+        result
+    };
     
-    sync_read::read_stream_data_loop(reader_half_mutex).await;
     let (result_send, result_receive) = tokio::join!(future_send, future_receive);
-    
-    result_send.unwrap_or_else(|e| {
-        let error_message = e.to_string();
-        let writer_half_mutex_clone = Arc::clone(&writer_half_mutex);
-        tokio::spawn(async move {
-            communication::send_rst(&writer_half_mutex_clone, Some(error_message.as_bytes().to_vec())).await;
-        });
-        eprintln!("Error receiving data: {}", e);
-    });
-    
-    result_receive.unwrap_or_else(|e| {
+
+    // A local failure to read the input or write the output resets the
+    // connection, so neither end takes the transfer for a complete one
+    if let Err(e) = &result_send {
         eprintln!("Error sending data: {}", e);
-    });
+    }
+    if let Err(e) = &result_receive {
+        eprintln!("Error receiving data: {}", e);
+    }
+    if let Some(e) = result_send.err().or(result_receive.err()) {
+        communication::send_rst(&session, Some(e.to_string().into_bytes())).await;
+    }
+    println!("Session stats: {}", session.stats());
+    println!("Connection state: {}", session.state().state());
 
-    writer_half_mutex.lock().await.shutdown().await.unwrap_or_else(|e| {
+    session.writer().shutdown().await.unwrap_or_else(|e| {
         eprintln!("Error shutting connection: {}", e);
     });
-    
+
+    session.state().state()
 }
 
 pub async fn handle_client_send(
-    stream_white: &Mutex<OwnedWriteHalf>,
+    session: &Session,
     input: &mut BufReader<File>,
+    mtu: usize,
+) -> std::io::Result<()> {
+    match session.config().format.arq {
+        Arq::StopAndWait => send_stop_and_wait(session, input, mtu).await?,
+        Arq::GoBackN | Arq::SelectiveRepeat => arq::send_windowed(session, input, mtu).await?,
+    }
+
+    println!("End send data!");
+    Ok(())
+}
+
+async fn send_stop_and_wait(
+    session: &Session,
+    input: &mut BufReader<File>,
+    mtu: usize,
 ) -> std::io::Result<()> {
     let mut id = network::START_ID;
 
     loop {
-        let mut read_buf = vec![0u8; network::MAX_DATA_SIZE];
+        let mut read_buf = vec![0u8; mtu];
 
         let bytes_read = input.read(&mut read_buf)?;
         if bytes_read == 0 {
-            communication::send_end(stream_white, id).await;
+            communication::send_end(session, id).await;
             break;
         }
 
-        let payload = Payload::new(read_buf[..bytes_read].to_vec(), id, network::FLAG_SED);
-        if let Err(e) = communication::send_frame(stream_white, &payload).await {
-            if e.kind == NetworkErrorKind::ConnectionError {
+        let payload = session.writer().payload(read_buf[..bytes_read].to_vec(), id, Flags::NONE);
+        if let Err(e) = communication::send_frame(session, &payload).await {
+            if e.kind == NetworkErrorKind::ConnectionError
+                || e.kind == NetworkErrorKind::ConnectionClosed
+            {
                 println!("Connection error: {}", e);
                 break;
             }
@@ -71,24 +137,28 @@ pub async fn handle_client_send(
             if e.kind == NetworkErrorKind::RetransmissionError {
                 break;
             }
+
+            if e.kind == NetworkErrorKind::RSTError || e.kind == NetworkErrorKind::StateError {
+                println!("Stop sending: {}", e);
+                break;
+            }
             continue;
         }
 
         id = communication::next_id(id);
     }
 
-    println!("End send data!");
     Ok(())
 }
 
 pub async fn handle_client_receive(
-    stream_white: &Mutex<OwnedWriteHalf>,
+    session: &Session,
     output: &mut BufWriter<File>,
 ) -> std::io::Result<()> {
-    let mut id: u16 = network::START_ID;
+    let mut receiver = Receiver::new(session.config());
 
     loop {
-        let payload = match communication::receive_frame(stream_white).await {
+        let payload = match receiver.receive(session).await {
             Ok(payload) => payload,
             Err(e) => {
                 if e.kind == NetworkErrorKind::UnexpectedFlagError {
@@ -99,7 +169,7 @@ pub async fn handle_client_receive(
             }
         };
 
-        if payload.flag == network::FLAG_END {
+        if payload.flag.contains(Flags::END) {
             if !payload.data.is_empty() {
                 output.write_all(&payload.data)?;
                 output.flush()?;
@@ -108,16 +178,23 @@ pub async fn handle_client_receive(
             break;
         }
 
-        if payload.id != id {
-            continue;
-        }
-
         output.write_all(&payload.data)?;
         output.flush()?;
-
-        id = communication::next_id(id);
     }
 
     println!("End receive data!");
     Ok(())
 }
+
+/// Fails unless both ends sent END, so a transfer cut short by a reset, a
+/// local error or a peer lost before both ENDs went through is not mistaken
+/// for a complete one. A peer closing the connection afterwards is fine.
+pub fn check_closed(state: ConnectionState) -> std::io::Result<()> {
+    match state {
+        ConnectionState::Closed => Ok(()),
+        state => Err(std::io::Error::new(
+            std::io::ErrorKind::ConnectionAborted,
+            format!("Connection ended in state {}", state),
+        )),
+    }
+}
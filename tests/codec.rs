@@ -0,0 +1,140 @@
+use std::{future::poll_fn, pin::Pin};
+
+use crate_net::dccnet::{
+    codec::DccnetCodec,
+    fec::Fec,
+    network::{Flags, FrameFormat, Payload, PayloadError, PayloadErrorKind},
+};
+use futures_core::Stream;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio_util::codec::FramedRead;
+
+fn frame(id: u16) -> Payload {
+    Payload::new(vec![id as u8; 10], id, Flags::NONE)
+}
+
+/// A reader that returns each of `reads` from a separate read call.
+fn reads(reads: Vec<Vec<u8>>) -> impl AsyncRead + Unpin {
+    reads.into_iter().map(std::io::Cursor::new).fold(
+        Box::new(tokio::io::empty()) as Box<dyn AsyncRead + Unpin>,
+        |reader, read| Box::new(reader.chain(read)),
+    )
+}
+
+async fn next<R: AsyncRead + Unpin>(
+    framed: &mut FramedRead<R, DccnetCodec>,
+) -> Option<Result<Payload, PayloadError>> {
+    poll_fn(|cx| Pin::new(&mut *framed).poll_next(cx))
+        .await
+        .map(|item| item.expect("no I/O error"))
+}
+
+async fn decode_all(
+    reader: impl AsyncRead + Unpin,
+    format: FrameFormat,
+) -> Vec<Result<Payload, PayloadError>> {
+    let mut framed = FramedRead::new(reader, DccnetCodec::with_format(format));
+    let mut items = Vec::new();
+    while let Some(item) = next(&mut framed).await {
+        items.push(item);
+    }
+    items
+}
+
+fn ids(items: &[Result<Payload, PayloadError>]) -> Vec<Result<u16, &PayloadErrorKind>> {
+    items
+        .iter()
+        .map(|item| {
+            item.as_ref()
+                .map(|payload| payload.id)
+                .map_err(|e| &e.kind)
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn frame_split_across_reads_is_reassembled() {
+    let bytes = frame(1).as_bytes();
+    let (head, tail) = bytes.split_at(12);
+
+    let items = decode_all(
+        reads(vec![head.to_vec(), tail.to_vec()]),
+        FrameFormat::default(),
+    )
+    .await;
+
+    assert_eq!(ids(&items), [Ok(1)]);
+    assert_eq!(items[0].as_ref().unwrap().data, frame(1).data);
+}
+
+#[tokio::test]
+async fn two_frames_in_one_read_are_both_decoded() {
+    let mut bytes = frame(1).as_bytes();
+    bytes.extend_from_slice(&frame(2).as_bytes());
+
+    let items = decode_all(reads(vec![bytes]), FrameFormat::default()).await;
+
+    assert_eq!(ids(&items), [Ok(1), Ok(2)]);
+}
+
+#[tokio::test]
+async fn oversized_length_is_rejected_and_decoding_resumes() {
+    let format = FrameFormat {
+        mtu: 8,
+        ..FrameFormat::default()
+    };
+    let small = Payload::new(vec![3; 4], 3, Flags::NONE);
+
+    let items = decode_all(reads(vec![frame(1).as_bytes(), small.as_bytes()]), format).await;
+
+    assert_eq!(ids(&items), [Err(&PayloadErrorKind::InvalidData), Ok(3)]);
+}
+
+#[tokio::test]
+async fn bad_checksum_is_rejected_and_decoding_resumes() {
+    let mut corrupted = frame(1).as_bytes();
+    *corrupted.last_mut().unwrap() ^= 0x01;
+
+    let items = decode_all(
+        reads(vec![corrupted, frame(2).as_bytes()]),
+        FrameFormat::default(),
+    )
+    .await;
+
+    assert_eq!(
+        ids(&items),
+        [Err(&PayloadErrorKind::ChecksumMismatch), Ok(2)]
+    );
+}
+
+#[tokio::test]
+async fn truncated_frame_at_end_of_stream_is_reported() {
+    let bytes = frame(1).as_bytes();
+
+    let items = decode_all(reads(vec![bytes[..20].to_vec()]), FrameFormat::default()).await;
+
+    assert_eq!(ids(&items), [Err(&PayloadErrorKind::InvalidPayload)]);
+}
+
+#[tokio::test]
+async fn bytes_repaired_by_fec_are_counted() {
+    let format = FrameFormat {
+        fec: Some(Fec::new(8).unwrap()),
+        ..FrameFormat::default()
+    };
+    let mut first = frame(1).as_bytes_with(&format);
+    first[15] ^= 0xFF;
+    first[20] ^= 0x01;
+    let mut second = frame(2).as_bytes_with(&format);
+    second[16] ^= 0x80;
+
+    let mut framed = FramedRead::new(reads(vec![first, second]), DccnetCodec::with_format(format));
+    let mut items = Vec::new();
+    while let Some(item) = next(&mut framed).await {
+        items.push(item);
+    }
+
+    assert_eq!(ids(&items), [Ok(1), Ok(2)]);
+    assert_eq!(items[0].as_ref().unwrap().data, frame(1).data);
+    assert_eq!(framed.decoder().corrected(), 3);
+}
@@ -0,0 +1,118 @@
+use crate_net::dccnet::{
+    detection::{Detection, ErrorDetector, InternetChecksum},
+    network::{self, Flags, FrameFormat, Payload, PayloadErrorKind},
+};
+use proptest::prelude::*;
+
+const LENGTH_FIELD: std::ops::Range<usize> = 10..12;
+
+fn detection() -> impl Strategy<Value = Detection> {
+    prop_oneof![
+        Just(Detection::Internet),
+        Just(Detection::Crc16),
+        Just(Detection::Crc32),
+    ]
+}
+
+fn frame() -> impl Strategy<Value = (Vec<u8>, u16, u8)> {
+    (
+        prop::collection::vec(any::<u8>(), 0..=network::MAX_DATA_SIZE),
+        any::<u16>(),
+        any::<u8>(),
+    )
+}
+
+/// Straightforward RFC 1071 sum used as a reference for the optimized version.
+fn reference_checksum(bytes: &[u8]) -> u16 {
+    let mut sum: u64 = bytes
+        .chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]) as u64)
+        .sum();
+
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+proptest! {
+    #[test]
+    fn new_round_trips_through_from_bytes((data, id, flag) in frame()) {
+        let payload = Payload::new(data.clone(), id, Flags::from_bits(flag));
+        let parsed = Payload::from_bytes(&payload.as_bytes()).unwrap();
+
+        prop_assert_eq!(parsed.chksum, payload.chksum);
+        prop_assert_eq!(parsed.length as usize, data.len());
+        prop_assert_eq!(parsed.id, id);
+        prop_assert_eq!(parsed.flag, Flags::from_bits(flag));
+        prop_assert_eq!(parsed.data, data);
+    }
+
+    #[test]
+    fn every_detector_round_trips((data, id, flag) in frame(), detection in detection()) {
+        let format = FrameFormat { detection, ..FrameFormat::default() };
+        let payload = Payload::with_format(data.clone(), id, Flags::from_bits(flag), &format);
+        let bytes = payload.as_bytes_with(&format);
+
+        prop_assert_eq!(bytes.len(), format.frame_size(data.len()));
+        prop_assert_eq!(Payload::from_bytes_with(&bytes, &format).unwrap().data, data);
+    }
+
+    #[test]
+    fn encoding_follows_the_given_format((data, id, flag) in frame(), detection in detection()) {
+        let format = FrameFormat { detection, ..FrameFormat::default() };
+        let bytes = Payload::new(data.clone(), id, Flags::from_bits(flag)).as_bytes_with(&format);
+
+        prop_assert_eq!(Payload::from_bytes_with(&bytes, &format).unwrap().data, data);
+    }
+
+    #[test]
+    fn single_bit_flip_is_detected(
+        (data, id, flag) in frame(),
+        detection in detection(),
+        position in any::<prop::sample::Index>(),
+        bit in 0..8u8,
+    ) {
+        let format = FrameFormat { detection, ..FrameFormat::default() };
+        let mut bytes = Payload::with_format(data, id, Flags::from_bits(flag), &format)
+            .as_bytes_with(&format);
+        let position = position.index(bytes.len());
+        bytes[position] ^= 1 << bit;
+
+        let kind = Payload::from_bytes_with(&bytes, &format).unwrap_err().kind;
+        if LENGTH_FIELD.contains(&position) {
+            // A length pointing past the buffer is indistinguishable from a truncated frame
+            prop_assert!(
+                kind == PayloadErrorKind::ChecksumMismatch || kind == PayloadErrorKind::InvalidData,
+                "unexpected {:?}",
+                kind
+            );
+        } else {
+            prop_assert!(
+                kind == PayloadErrorKind::ChecksumMismatch || kind == PayloadErrorKind::InvalidSync,
+                "unexpected {:?}",
+                kind
+            );
+        }
+    }
+
+    #[test]
+    fn odd_length_is_padded_with_zero(mut bytes in prop::collection::vec(any::<u8>(), 0..512)) {
+        if bytes.len() % 2 == 0 {
+            bytes.push(0xFF);
+        }
+        let checksum = InternetChecksum.compute(&bytes);
+
+        prop_assert_eq!(checksum, reference_checksum(&bytes) as u32);
+        bytes.push(0);
+        prop_assert_eq!(checksum, InternetChecksum.compute(&bytes));
+    }
+
+    #[test]
+    fn odd_length_data_round_trips(data in prop::collection::vec(any::<u8>(), 0..512), id in any::<u16>()) {
+        let data = if data.len() % 2 == 0 { [data, vec![0x5A]].concat() } else { data };
+        let bytes = Payload::new(data.clone(), id, Flags::NONE).as_bytes();
+
+        prop_assert_eq!(Payload::from_bytes(&bytes).unwrap().data, data);
+    }
+}
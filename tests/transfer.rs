@@ -0,0 +1,309 @@
+use std::{
+    fs::{self, File},
+    future::poll_fn,
+    io::{BufReader, BufWriter},
+    path::PathBuf,
+    pin::Pin,
+    time::Duration,
+};
+
+use crate_net::dccnet::{
+    arq::Arq,
+    codec::DccnetCodec,
+    config::DccnetConfig,
+    detection::Detection,
+    impairment::Impairment,
+    network::{Flags, Payload},
+    options::FrameOption,
+    state::ConnectionState,
+    xfer,
+};
+use futures_core::Stream;
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpListener, TcpStream, tcp::OwnedWriteHalf},
+    sync::{Mutex, Notify, mpsc},
+};
+use tokio_util::codec::FramedRead;
+
+const FILE_SIZE: usize = 64 * 1024;
+
+/// Bytes that differ between the two ends, so a frame delivered to the wrong
+/// file or out of order does not go unnoticed.
+fn contents(seed: u64) -> Vec<u8> {
+    let mut state = seed;
+    (0..FILE_SIZE)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 56) as u8
+        })
+        .collect()
+}
+
+fn temp_path(test: &str, name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("dccnet-{}-{}-{}", std::process::id(), test, name))
+}
+
+/// Runs a full-duplex transfer over loopback and checks that both files came
+/// through byte for byte and the connection closed cleanly on both ends.
+async fn transfer(test: &str, config: DccnetConfig) {
+    transfer_between(test, &config, &config).await;
+}
+
+/// Like `transfer`, with different settings on each end.
+async fn transfer_between(test: &str, server_config: &DccnetConfig, client_config: &DccnetConfig) {
+    let server_in = temp_path(test, "server.in");
+    let server_out = temp_path(test, "server.out");
+    let client_in = temp_path(test, "client.in");
+    let client_out = temp_path(test, "client.out");
+    fs::write(&server_in, contents(1)).unwrap();
+    fs::write(&client_in, contents(2)).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = async {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut input = BufReader::new(File::open(&server_in).unwrap());
+        let mut output = BufWriter::new(File::create(&server_out).unwrap());
+        xfer::handle_connection(stream, &mut input, &mut output, server_config).await
+    };
+    let client = async {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut input = BufReader::new(File::open(&client_in).unwrap());
+        let mut output = BufWriter::new(File::create(&client_out).unwrap());
+        xfer::handle_connection(stream, &mut input, &mut output, client_config).await
+    };
+    let (server_state, client_state) = tokio::join!(server, client);
+
+    let server_got = fs::read(&server_out).unwrap();
+    let client_got = fs::read(&client_out).unwrap();
+    for path in [&server_in, &server_out, &client_in, &client_out] {
+        let _ = fs::remove_file(path);
+    }
+
+    assert!(
+        server_got == contents(2),
+        "server output differs from client input"
+    );
+    assert!(
+        client_got == contents(1),
+        "client output differs from server input"
+    );
+    assert_eq!(server_state, ConnectionState::Closed);
+    assert_eq!(client_state, ConnectionState::Closed);
+}
+
+/// Corrupts about one frame in six in each direction, with a detector that
+/// catches every error pattern the test is likely to produce. The few attempts
+/// allowed leave no room for counting the frames Go-Back-N resends behind a
+/// lost one.
+fn impaired(arq: Arq) -> DccnetConfig {
+    let mut config = DccnetConfig {
+        ack_timeout: Duration::from_millis(100),
+        max_rto: Duration::from_millis(200),
+        linger: Duration::from_millis(200),
+        max_send_attempts: 4,
+        impairment: Impairment {
+            outgoing: 0.00002,
+            incoming: 0.00002,
+            seed: 7,
+        },
+        ..DccnetConfig::default()
+    };
+    config.format.mtu = 512;
+    config.format.arq = arq;
+    config.format.detection = Detection::Crc32;
+    // A lost END would otherwise leave the peer waiting for its read timeout
+    config.format.end_ack = true;
+    config
+}
+
+#[tokio::test]
+async fn go_back_n_recovers_from_bit_errors() {
+    transfer("go-back-n", impaired(Arq::GoBackN)).await;
+}
+
+#[tokio::test]
+async fn selective_repeat_recovers_from_bit_errors() {
+    transfer("selective-repeat", impaired(Arq::SelectiveRepeat)).await;
+}
+
+async fn write(writer: &Mutex<OwnedWriteHalf>, payload: Payload) {
+    writer
+        .lock()
+        .await
+        .write_all(&payload.as_bytes())
+        .await
+        .unwrap();
+}
+
+/// A peer that follows the specification to the letter: stop-and-wait with
+/// alternating ids, END sent once and never acknowledged, and the connection
+/// closed as soon as both ENDs went through. Its data frames carry `flags`.
+///
+/// With an `mtu` it negotiates that MTU first, and sends its OPT again once it
+/// was answered, as if the answer got lost.
+async fn spec_peer(stream: TcpStream, input: Vec<u8>, flags: Flags, mtu: Option<u16>) -> Vec<u8> {
+    let (reader, writer) = stream.into_split();
+    let writer = Mutex::new(writer);
+    let (acks, mut acked) = mpsc::unbounded_channel();
+    let (answers, mut answered) = mpsc::unbounded_channel();
+    let opt = |flag| {
+        Payload::new(
+            FrameOption::encode_all(&[FrameOption::Mtu(mtu.unwrap())]),
+            u16::MAX,
+            flag,
+        )
+    };
+    let ended = Notify::new();
+    let mut output = Vec::new();
+
+    let receive = async {
+        let mut framed = FramedRead::new(reader, DccnetCodec::new());
+        let mut expected = 0;
+        while let Some(item) = poll_fn(|cx| Pin::new(&mut framed).poll_next(cx)).await {
+            let payload = item.unwrap().unwrap();
+            if mtu.is_some() && payload.flag == Flags::OPT {
+                write(&writer, opt(Flags::ACK | Flags::OPT)).await;
+            } else if mtu.is_some() && payload.flag == Flags::ACK | Flags::OPT {
+                let _ = answers.send(());
+            } else if payload.flag.contains(Flags::ACK) {
+                let _ = acks.send(payload.id);
+            } else if payload.flag.contains(Flags::END) {
+                output.extend_from_slice(&payload.data);
+                ended.notify_one();
+            } else {
+                if let Some(mtu) = mtu {
+                    assert!(payload.data.len() <= mtu as usize, "frame over the MTU");
+                }
+                if payload.id == expected {
+                    output.extend_from_slice(&payload.data);
+                    expected = 1 - expected;
+                }
+                write(&writer, Payload::new(vec![], payload.id, Flags::ACK)).await;
+            }
+        }
+    };
+    let send = async {
+        if mtu.is_some() {
+            for _ in 0..2 {
+                write(&writer, opt(Flags::OPT)).await;
+                answered.recv().await.unwrap();
+            }
+        }
+        let mut id = 0;
+        for chunk in input.chunks(1024) {
+            write(&writer, Payload::new(chunk.to_vec(), id, flags)).await;
+            while acked.recv().await.unwrap() != id {}
+            id = 1 - id;
+        }
+        write(&writer, Payload::new(vec![], id, Flags::END)).await;
+        ended.notified().await;
+    };
+    tokio::select! {
+        () = receive => {}
+        () = send => {}
+    }
+    output
+}
+
+/// Runs a transfer against `spec_peer`, which must close cleanly.
+async fn transfer_with_spec_peer(test: &str, config: DccnetConfig, flags: Flags, mtu: Option<u16>) {
+    let input = temp_path(test, "client.in");
+    let output = temp_path(test, "client.out");
+    fs::write(&input, contents(2)).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = async {
+        let (stream, _) = listener.accept().await.unwrap();
+        spec_peer(stream, contents(1), flags, mtu).await
+    };
+    let client = async {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut input = BufReader::new(File::open(&input).unwrap());
+        let mut output = BufWriter::new(File::create(&output).unwrap());
+        xfer::handle_connection(stream, &mut input, &mut output, &config).await
+    };
+    let (server_got, client_state) = tokio::join!(server, client);
+
+    let client_got = fs::read(&output).unwrap();
+    for path in [&input, &output] {
+        let _ = fs::remove_file(path);
+    }
+
+    assert!(
+        server_got == contents(2),
+        "peer output differs from client input"
+    );
+    assert!(
+        client_got == contents(1),
+        "client output differs from peer input"
+    );
+    assert_eq!(client_state, ConnectionState::Closed);
+    assert!(xfer::check_closed(client_state).is_ok());
+}
+
+/// The peer closing the connection right after both ENDs is a clean close,
+/// with or without END-ACK on our side.
+#[tokio::test]
+async fn peer_that_does_not_ack_end_closes_cleanly() {
+    transfer_with_spec_peer("spec-peer", DccnetConfig::default(), Flags::NONE, None).await;
+}
+
+#[tokio::test]
+async fn peer_that_does_not_ack_end_closes_cleanly_with_end_ack() {
+    let mut config = DccnetConfig {
+        ack_timeout: Duration::from_millis(100),
+        max_rto: Duration::from_millis(200),
+        max_send_attempts: 2,
+        ..DccnetConfig::default()
+    };
+    config.format.end_ack = true;
+    transfer_with_spec_peer("spec-peer-end-ack", config, Flags::NONE, None).await;
+}
+
+/// Without NAK enabled, the NAK bit of a data frame is not taken for a frame
+/// that arrived corrupted.
+#[tokio::test]
+async fn nak_bit_is_ignored_without_nak() {
+    transfer_with_spec_peer("nak-bit", DccnetConfig::default(), Flags::NAK, None).await;
+}
+
+fn negotiating(mtu: usize) -> DccnetConfig {
+    let mut config = DccnetConfig::default();
+    config.format.mtu = mtu;
+    config.format.negotiate_mtu = true;
+    config
+}
+
+/// Each end only accepts frames up to its own MTU, so the transfer goes
+/// through only if both agreed on the smaller one.
+#[tokio::test]
+async fn ends_agree_on_the_smaller_mtu() {
+    transfer_between("negotiate", &negotiating(512), &negotiating(2048)).await;
+}
+
+/// The peer sends its OPT again after this end started the transfer, which the
+/// receive path answers.
+#[tokio::test]
+async fn mtu_negotiation_answers_a_resent_opt() {
+    transfer_with_spec_peer(
+        "negotiate-resent",
+        negotiating(2048),
+        Flags::NONE,
+        Some(1024),
+    )
+    .await;
+}
+
+/// A peer that does not negotiate starts sending data, and this end falls back
+/// to the specification MTU.
+#[tokio::test]
+async fn mtu_negotiation_falls_back_without_peer_support() {
+    transfer_with_spec_peer("negotiate-spec-peer", negotiating(8192), Flags::NONE, None).await;
+}